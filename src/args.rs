@@ -0,0 +1,75 @@
+//! Command line arguments of the offline tools
+//!
+//! The tools share the flags `--board` and `--rules`, and take the moves that lead from the start
+//! of the game to the position they work on.
+
+use crate::{
+    geometry::{Board, Geometry},
+    parse_move,
+    rules::Rules,
+    Game, GameResult, History,
+};
+use std::{collections::HashMap, error::Error};
+
+/// Command line arguments of the offline tools
+pub(crate) struct Args<'a> {
+    pub(crate) board: Board,
+    pub(crate) rules: Rules,
+    /// Values of the flags other than `--board` and `--rules`
+    pub(crate) flags: HashMap<&'a str, &'a str>,
+    /// Moves that lead from the start of the game to a position
+    pub(crate) moves: Vec<&'a str>,
+}
+
+impl<'a> Args<'a> {
+    /// Parse arguments of the form `[--board <BOARD>] [--rules <RULES>] [<FLAG> <VALUE>...]
+    /// [<MOVE>...]`, where `flags` lists the other flags that are accepted
+    pub(crate) fn parse(args: &'a [String], flags: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let mut parsed = Args {
+            board: Board::Standard,
+            rules: Rules::standard(),
+            flags: HashMap::new(),
+            moves: vec![],
+        };
+        let mut args = args.iter().map(String::as_str);
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                parsed.moves.push(arg);
+                continue;
+            }
+            let value = args.next().ok_or(format!("missing value of `{}`", arg))?;
+            match arg {
+                "--board" => {
+                    parsed.board =
+                        Board::parse(value).ok_or(format!("unknown board `{}`", value))?;
+                }
+                "--rules" => {
+                    parsed.rules =
+                        Rules::parse(value).ok_or(format!("unknown rules `{}`", value))?;
+                }
+                _ if flags.contains(&arg) => {
+                    parsed.flags.insert(arg, value);
+                }
+                _ => return Err(format!("unknown flag `{}`", arg).into()),
+            }
+        }
+        if parsed.rules.calling {
+            return Err("quartos that have to be called aren't supported".into());
+        }
+        Ok(parsed)
+    }
+
+    /// Play the moves from the start of the game, and return the game after them
+    pub(crate) fn replay<G: Geometry>(&self) -> Result<Game<G>, Box<dyn Error>> {
+        let mut game = Game::new(History::<G>::new(self.rules));
+        for text in &self.moves {
+            let mv = parse_move::<G>(text).map_err(|err| format!("{} in `{}`", err, text))?;
+            game.apply(&mv)
+                .map_err(|err| format!("illegal move `{}`, {}", text, err))?;
+        }
+        if game.outcome() != GameResult::InProgress {
+            return Err("the game is already over".into());
+        }
+        Ok(game)
+    }
+}
@@ -0,0 +1,222 @@
+//! Opening book, with the moves to play in the first turns of the game
+//!
+//! The first turns are where a search spreads its iterations the thinnest, since each position
+//! has the most moves. A book is made once with long searches, and then Bruto picks its moves
+//! from it at random, weighted by how often the searches visited them.
+//!
+//! Moves are stored as the canonical keys of the positions they lead to, so that a book entry
+//! applies to all the positions which are equivalent to the one it was made for. The file is
+//! text, starting with a line with the format version, the board and the rules. Each of the
+//! other lines holds the key of a position, the key of a position that a move leads to, and
+//! the weight of that move, with the keys in hexadecimal.
+
+use crate::{
+    args::Args,
+    geometry::{Board, Dims, Geometry},
+    rules::Rules,
+    symmetry::Symmetries,
+    tablebase::{next_positions, Next},
+    Bruto, Engine, Game, GameResult, Move, Pcg, SearchControl,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    io::{self, Write},
+    sync::atomic::AtomicBool,
+    time::Instant,
+};
+
+/// Version of the file format
+const VERSION: u32 = 1;
+
+/// Run the generator with command line arguments of the form
+/// `--plies <N> --iterations <N> --width <N> --out <FILE> [--board <BOARD>] [--rules <RULES>]
+/// [<MOVE>...]`
+///
+/// Starting from the position that the moves lead to, each position is searched, and the search
+/// goes on after the `--width` moves that were visited the most, for `--plies` turns.
+pub fn run(args: &[String], output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["--plies", "--iterations", "--width", "--out"])?;
+    let number = |flag: &str| -> Result<u32, Box<dyn Error>> {
+        let value = args.flags.get(flag).ok_or(format!("missing `{}`", flag))?;
+        Ok(value
+            .parse()
+            .map_err(|_| format!("invalid value of `{}`", flag))?)
+    };
+    let plies = number("--plies")? as i8;
+    let iterations = number("--iterations")?;
+    let width = number("--width")? as usize;
+    let path = *args.flags.get("--out").ok_or("missing `--out`")?;
+    match args.board {
+        Board::Small => generate::<Dims<3, 3>>(&args, plies, iterations, width, path, output),
+        Board::Standard => generate::<Dims<4, 4>>(&args, plies, iterations, width, path, output),
+        Board::Large => Err("the board is too large for a book".into()),
+    }
+}
+
+fn generate<G: Geometry>(
+    args: &Args,
+    plies: i8,
+    iterations: u32,
+    width: usize,
+    path: &str,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let game = args.replay::<G>()?;
+    let start = Instant::now();
+    let mut book = Book {
+        rules: args.rules,
+        entries: HashMap::new(),
+        symmetries: Symmetries::new(args.rules.squares),
+    };
+    let stop = AtomicBool::new(false);
+    let mut bruto = Bruto::<G>::new();
+    bruto.iterations = iterations;
+
+    let last_turn = (game.turn() + plies).min(G::LAST_TURN + 1);
+    let mut searched = HashSet::new();
+    let mut queue = vec![game];
+    while let Some(game) = queue.pop() {
+        let key = book.symmetries.canonical_key(game.position());
+        if game.turn() >= last_turn || !searched.insert(key) {
+            continue;
+        }
+        bruto.play(&game, &mut SearchControl::new(&stop));
+        // add up the visits of the moves that lead to equivalent positions
+        let mut children: Vec<(u128, u32, Move)> = vec![];
+        for (mv, visits, _) in bruto.root_children(game.turn()) {
+            let mut next = game.clone();
+            next.apply(&mv).unwrap();
+            if visits == 0 || next.outcome() != GameResult::InProgress {
+                continue;
+            }
+            let key = book.symmetries.canonical_key(next.position());
+            match children.iter_mut().find(|(other, _, _)| *other == key) {
+                Some((_, weight, _)) => *weight += visits,
+                None => children.push((key, visits, mv)),
+            }
+        }
+        children.sort_by(|a, b| b.1.cmp(&a.1));
+        for (_, _, mv) in children.iter().take(width) {
+            let mut next = game.clone();
+            next.apply(mv).unwrap();
+            queue.push(next);
+        }
+        let moves = children
+            .iter()
+            .map(|(child, weight, _)| (*child, *weight))
+            .collect();
+        book.entries.insert(key, moves);
+    }
+
+    book.save(path)?;
+    writeln!(output, "positions {}", book.entries.len())?;
+    writeln!(output, "time {:.3}s", start.elapsed().as_secs_f32())?;
+    Ok(())
+}
+
+pub(crate) struct Book<G: Geometry> {
+    pub(crate) rules: Rules,
+    /// Keys of the positions that the moves lead to, with their weights, by canonical key
+    entries: HashMap<u128, Vec<(u128, u32)>>,
+    symmetries: Symmetries<G>,
+}
+
+impl<G: Geometry> Book<G> {
+    fn header(rules: Rules) -> String {
+        format!("bruto book {} {} {}", VERSION, G::BOARD, rules)
+    }
+
+    fn save(&self, path: &str) -> io::Result<()> {
+        let mut lines = vec![Self::header(self.rules)];
+        let mut keys: Vec<&u128> = self.entries.keys().collect();
+        keys.sort_unstable();
+        for key in keys {
+            for (child, weight) in &self.entries[key] {
+                lines.push(format!("{:x} {:x} {}", key, child, weight));
+            }
+        }
+        fs::write(path, lines.join("\n") + "\n")
+    }
+
+    /// Load a book for this board
+    pub(crate) fn load(path: &str) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        let header = lines.next().unwrap_or_default();
+        let words: Vec<&str> = header.split_whitespace().collect();
+        let rules = match words[..] {
+            ["bruto", "book", version, ..] if version != VERSION.to_string() => {
+                Err(invalid("the book has an unsupported version".to_string()))
+            }
+            ["bruto", "book", _, board, ..] if board != G::BOARD.to_string() => {
+                Err(invalid("the book is for another board".to_string()))
+            }
+            ["bruto", "book", _, _, ref rules @ ..] => Rules::parse(&rules.join(" "))
+                .ok_or_else(|| invalid("the book has an invalid header".to_string())),
+            _ => Err(invalid("the book has an invalid header".to_string())),
+        }?;
+
+        let mut entries: HashMap<u128, Vec<(u128, u32)>> = HashMap::new();
+        for (number, line) in lines.enumerate() {
+            let parsed = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [key, child, weight] => u128::from_str_radix(key, 16)
+                    .ok()
+                    .zip(u128::from_str_radix(child, 16).ok())
+                    .zip(weight.parse().ok()),
+                _ => None,
+            };
+            let ((key, child), weight) = parsed
+                .ok_or_else(|| invalid(format!("the book has an invalid line {}", number + 2)))?;
+            entries.entry(key).or_default().push((child, weight));
+        }
+        Ok(Self {
+            rules,
+            entries,
+            symmetries: Symmetries::new(rules.squares),
+        })
+    }
+
+    /// Pick a move from the book at random, weighted by the moves' weights
+    ///
+    /// Returns None if the position isn't in the book.
+    pub(crate) fn pick_move(&self, game: &Game<G>, pcg: &mut Pcg) -> Option<Move> {
+        let position = game.position();
+        let book_moves = self.entries.get(&self.symmetries.canonical_key(position))?;
+        // the moves that lead to positions in the book, with the keys of those positions
+        let mut moves: Vec<(Move, u128)> = vec![];
+        for (mv, next) in next_positions(position, game.turn(), self.rules) {
+            if let Next::Position(next) = next {
+                let key = self.symmetries.canonical_key(&next);
+                if book_moves.iter().any(|(child, _)| *child == key) {
+                    moves.push((mv, key));
+                }
+            }
+        }
+        let reached: Vec<(u128, u32)> = book_moves
+            .iter()
+            .filter(|(child, weight)| *weight > 0 && moves.iter().any(|(_, key)| key == child))
+            .copied()
+            .collect();
+        let total: u64 = reached.iter().map(|(_, weight)| *weight as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        // pick a position by weight, and then one of the equivalent moves that lead to it
+        let mut choice = pcg.next_u64() % total;
+        let (key, _) = reached.into_iter().find(|(_, weight)| {
+            let found = choice < *weight as u64;
+            choice = choice.saturating_sub(*weight as u64);
+            found
+        })?;
+        let mut equivalent: Vec<Move> = moves
+            .into_iter()
+            .filter(|(_, other)| *other == key)
+            .map(|(mv, _)| mv)
+            .collect();
+        let index = pcg.next_u64() % equivalent.len() as u64;
+        Some(equivalent.swap_remove(index as usize))
+    }
+}
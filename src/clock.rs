@@ -0,0 +1,97 @@
+//! Chess clocks, which limit the time each player has for their moves
+//!
+//! The clocks start after the first move, since giving the first piece doesn't take any thought.
+
+use std::{fmt, time::Duration};
+
+/// How much time the players have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TimeControl {
+    /// Each player starts with `base`, and gets `increment` added after each of their moves
+    Increment { base: Duration, increment: Duration },
+    /// Each move has to be made within this time
+    PerMove(Duration),
+}
+
+impl TimeControl {
+    /// Parse a time control of the form `<BASE>+<INCREMENT>`, `<BASE>` or `move <TIME>`,
+    /// with times in seconds, e.g. `300+5`
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        let seconds = |text: &str| {
+            let seconds: f64 = text.parse().ok()?;
+            (seconds.is_finite() && seconds >= 0.0).then(|| Duration::from_secs_f64(seconds))
+        };
+        match text.split_whitespace().collect::<Vec<_>>()[..] {
+            ["move", time] => Some(TimeControl::PerMove(seconds(time)?)),
+            [time] => {
+                let (base, increment) = time.split_once('+').unwrap_or((time, "0"));
+                Some(TimeControl::Increment {
+                    base: seconds(base)?,
+                    increment: seconds(increment)?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeControl::Increment { base, increment } => {
+                write!(f, "{}+{}", base.as_secs_f32(), increment.as_secs_f32())
+            }
+            TimeControl::PerMove(time) => write!(f, "move {}", time.as_secs_f32()),
+        }
+    }
+}
+
+/// The clocks of both players, where player 0 moves first
+pub(crate) struct Clock {
+    pub(crate) control: TimeControl,
+    remaining: [Duration; 2],
+}
+
+impl Clock {
+    pub(crate) fn new(control: TimeControl) -> Self {
+        let base = match control {
+            TimeControl::Increment { base, .. } => base,
+            TimeControl::PerMove(time) => time,
+        };
+        Self {
+            control,
+            remaining: [base; 2],
+        }
+    }
+
+    /// The time `player` has for their current move
+    pub(crate) fn time_left(&self, player: usize) -> Duration {
+        self.remaining[player]
+    }
+
+    /// Charge `player` for a move that took `elapsed`
+    ///
+    /// Returns false if they ran out of time.
+    pub(crate) fn charge(&mut self, player: usize, elapsed: Duration) -> bool {
+        if elapsed > self.remaining[player] {
+            self.remaining[player] = Duration::ZERO;
+            return false;
+        }
+        if let TimeControl::Increment { increment, .. } = self.control {
+            self.remaining[player] = self.remaining[player] - elapsed + increment;
+        }
+        true
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "clock {} player 1 {:.1}s player 2 {:.1}s",
+            self.control,
+            self.remaining[0].as_secs_f32(),
+            self.remaining[1].as_secs_f32()
+        )
+    }
+}
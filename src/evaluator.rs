@@ -0,0 +1,425 @@
+//! Learned evaluator, a small model that rates positions and moves in place of random playouts
+//!
+//! The model is linear over hand-made features of the bitboards, so it runs fast on a CPU and
+//! trains in seconds. Its value part is a logistic regression that gives the chance that the
+//! player to move wins, where a draw counts as half a win. Its policy part scores each move,
+//! and a softmax over the legal moves turns the scores into the priors of a PUCT search.
+//!
+//! Most features are about threats: groups that lack one piece to be a quarto, and the
+//! attribute values that would complete them. A piece with such a value is unsafe to give.
+//!
+//! The weights are trained on self-play data, and kept in a text file that starts with a line
+//! with the format version, the board and the rules, followed by a line of value weights and a
+//! line of policy weights.
+
+use crate::{
+    args::Args,
+    geometry::{Board, BoardBits, Dims, Geometry},
+    parse_move,
+    rules::Rules,
+    selfplay, Move, Pcg, Piece, Position, Spot,
+};
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    marker::PhantomData,
+    time::Instant,
+};
+
+/// Version of the file format
+const VERSION: u32 = 1;
+
+/// Run the training with command line arguments of the form
+/// `--data <FILE> --out <FILE> [--epochs <N>] [--rate <X>] [--board <BOARD>] [--rules <RULES>]`
+///
+/// The data is a file of the self-play generator, for the same board and rules.
+pub fn run(args: &[String], output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["--data", "--out", "--epochs", "--rate"])?;
+    if !args.moves.is_empty() {
+        return Err("the training doesn't take moves".into());
+    }
+    let data = *args.flags.get("--data").ok_or("missing `--data`")?;
+    let path = *args.flags.get("--out").ok_or("missing `--out`")?;
+    let epochs = match args.flags.get("--epochs") {
+        Some(epochs) => epochs.parse().map_err(|_| "invalid value of `--epochs`")?,
+        None => 10,
+    };
+    let rate = match args.flags.get("--rate") {
+        Some(rate) => rate.parse().map_err(|_| "invalid value of `--rate`")?,
+        None => 0.01,
+    };
+    match args.board {
+        Board::Small => train::<Dims<3, 3>>(args.rules, data, epochs, rate, path, output),
+        Board::Standard => train::<Dims<4, 4>>(args.rules, data, epochs, rate, path, output),
+        Board::Large => train::<Dims<5, 5>>(args.rules, data, epochs, rate, path, output),
+    }
+}
+
+/// A position of the training data, with its features and targets
+struct Sample {
+    value_features: Vec<f32>,
+    /// 1 for a win of the player to move, 0.5 for a draw, and 0 for a loss
+    value_target: f32,
+    /// The features of each move of the search, with its share of the visits
+    moves: Vec<(Vec<f32>, f32)>,
+}
+
+fn train<G: Geometry>(
+    rules: Rules,
+    data: &str,
+    epochs: u32,
+    rate: f32,
+    path: &str,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let mut evaluator = Evaluator::<G> {
+        rules,
+        value_weights: vec![0.0; value_feature_count::<G>()],
+        policy_weights: vec![0.0; policy_feature_count::<G>()],
+        geometry: PhantomData,
+    };
+    let samples = load_samples(&evaluator, data)?;
+    if samples.is_empty() {
+        return Err("the data has no positions".into());
+    }
+    let mut pcg = Pcg::new();
+    let mut order: Vec<usize> = (0..samples.len()).collect();
+    for epoch in 0..epochs {
+        // shuffle the samples, so that the positions of a game aren't seen in a row
+        for i in (1..order.len()).rev() {
+            order.swap(i, (pcg.next_u64() % (i as u64 + 1)) as usize);
+        }
+        let mut value_loss = 0.0;
+        let mut policy_loss = 0.0;
+        for &i in &order {
+            let sample = &samples[i];
+            // logistic regression of the result
+            let value = sigmoid(dot(&evaluator.value_weights, &sample.value_features));
+            value_loss -= sample.value_target * value.max(1e-6).ln()
+                + (1.0 - sample.value_target) * (1.0 - value).max(1e-6).ln();
+            let error = value - sample.value_target;
+            for (weight, feature) in evaluator
+                .value_weights
+                .iter_mut()
+                .zip(&sample.value_features)
+            {
+                *weight -= rate * error * feature;
+            }
+            // softmax regression of the visits
+            if sample.moves.is_empty() {
+                continue;
+            }
+            let scores: Vec<f32> = sample
+                .moves
+                .iter()
+                .map(|(features, _)| dot(&evaluator.policy_weights, features))
+                .collect();
+            let priors = softmax(&scores);
+            for ((features, target), prior) in sample.moves.iter().zip(&priors) {
+                policy_loss -= target * prior.max(1e-6).ln();
+                for (weight, feature) in evaluator.policy_weights.iter_mut().zip(features) {
+                    *weight -= rate * (prior - target) * feature;
+                }
+            }
+        }
+        writeln!(
+            output,
+            "epoch {} value loss {:.4} policy loss {:.4}",
+            epoch + 1,
+            value_loss / samples.len() as f32,
+            policy_loss / samples.len() as f32
+        )?;
+    }
+    evaluator.save(path)?;
+    writeln!(output, "positions {}", samples.len())?;
+    writeln!(output, "time {:.3}s", start.elapsed().as_secs_f32())?;
+    Ok(())
+}
+
+/// Read the positions of a self-play file, with their features
+fn load_samples<G: Geometry>(evaluator: &Evaluator<G>, path: &str) -> io::Result<Vec<Sample>> {
+    let text = fs::read_to_string(path)?;
+    let (rules, _) = selfplay::read_header::<G>(&text)?;
+    if rules != evaluator.rules {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the data is for other rules",
+        ));
+    }
+    let mut samples = vec![];
+    // skip the header
+    for (number, line) in text.lines().enumerate().skip(2) {
+        let sample = parse_sample(evaluator, line).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the data has an invalid line {}", number + 1),
+            )
+        })?;
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+/// Parse a line of the form `game,turn,pieces,mask,selected,result,visits`
+fn parse_sample<G: Geometry>(evaluator: &Evaluator<G>, line: &str) -> Option<Sample> {
+    let columns: Vec<&str> = line.split(',').collect();
+    let (pieces, mask, selected, result, visits) = match columns[..] {
+        [_, _, pieces, mask, selected, result, visits] => (pieces, mask, selected, result, visits),
+        _ => return None,
+    };
+    let selected_piece = match selected.parse().ok()? {
+        -1 => None,
+        piece if (0..G::PIECES).contains(&piece) => Some(Piece(piece)),
+        _ => return None,
+    };
+    let position = Position::<G> {
+        board_pieces: parse_bits(pieces)?,
+        board_mask: parse_bits(mask)?,
+        selected_piece,
+        rules: evaluator.rules,
+    };
+    let value_target = match result {
+        "1" => 1.0,
+        "0" => 0.5,
+        "-1" => 0.0,
+        _ => return None,
+    };
+    let mut moves = vec![];
+    let mut counts = vec![];
+    for pair in visits.split_whitespace() {
+        let (mv, count) = pair.split_once(':')?;
+        moves.push(parse_move::<G>(mv).ok()?);
+        counts.push(count.parse::<u32>().ok()?);
+    }
+    let total: u32 = counts.iter().sum();
+    let moves = if total > 0 {
+        evaluator
+            .policy_features(&position, &moves)
+            .into_iter()
+            .zip(counts)
+            .map(|(features, count)| (features, count as f32 / total as f32))
+            .collect()
+    } else {
+        vec![]
+    };
+    Some(Sample {
+        value_features: evaluator.value_features(&position),
+        value_target,
+        moves,
+    })
+}
+
+/// Parse a bitboard in hexadecimal
+fn parse_bits<B: BoardBits>(text: &str) -> Option<B> {
+    text.chars().try_fold(B::ZERO, |bits, c| {
+        Some(bits << 4 | B::from_i8(c.to_digit(16)? as i8))
+    })
+}
+
+fn dot(weights: &[f32], features: &[f32]) -> f32 {
+    weights.iter().zip(features).map(|(w, x)| w * x).sum()
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+fn softmax(scores: &[f32]) -> Vec<f32> {
+    let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = scores.iter().map(|score| (score - max).exp()).collect();
+    let total: f32 = exps.iter().sum();
+    exps.iter().map(|e| e / total).collect()
+}
+
+/// Number of value features: a bias, whether the player to move can complete a quarto, the
+/// number of kinds of threats, the share of unsafe pieces, how full the board is, the parity of
+/// the empty spots, and whether each spot is occupied
+fn value_feature_count<G: Geometry>() -> usize {
+    6 + G::SPOTS as usize
+}
+
+/// Number of policy features: whether the move completes a quarto, whether it places the last
+/// piece without one, whether the piece it gives is unsafe, the number of kinds of threats and
+/// the share of unsafe pieces it leaves, and which spot it places on
+fn policy_feature_count<G: Geometry>() -> usize {
+    5 + G::SPOTS as usize
+}
+
+pub(crate) struct Evaluator<G: Geometry> {
+    pub(crate) rules: Rules,
+    value_weights: Vec<f32>,
+    policy_weights: Vec<f32>,
+    geometry: PhantomData<G>,
+}
+
+impl<G: Geometry> Evaluator<G> {
+    fn header(rules: Rules) -> String {
+        format!("bruto evaluator {} {} {}", VERSION, G::BOARD, rules)
+    }
+
+    fn save(&self, path: &str) -> io::Result<()> {
+        let weights =
+            |weights: &[f32]| -> String { weights.iter().map(|w| format!(" {}", w)).collect() };
+        let text = format!(
+            "{}\nvalue{}\npolicy{}\n",
+            Self::header(self.rules),
+            weights(&self.value_weights),
+            weights(&self.policy_weights)
+        );
+        fs::write(path, text)
+    }
+
+    /// Load an evaluator for this board
+    pub(crate) fn load(path: &str) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        let header = lines.next().unwrap_or_default();
+        let words: Vec<&str> = header.split_whitespace().collect();
+        let rules = match words[..] {
+            ["bruto", "evaluator", version, ..] if version != VERSION.to_string() => {
+                Err(invalid("the evaluator has an unsupported version"))
+            }
+            ["bruto", "evaluator", _, board, ..] if board != G::BOARD.to_string() => {
+                Err(invalid("the evaluator is for another board"))
+            }
+            ["bruto", "evaluator", _, _, ref rules @ ..] => Rules::parse(&rules.join(" "))
+                .ok_or_else(|| invalid("the evaluator has an invalid header")),
+            _ => Err(invalid("the evaluator has an invalid header")),
+        }?;
+        let mut weights = |name: &str, count: usize| {
+            let line = lines.next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let weights: Vec<f32> = match words.next() {
+                Some(word) if word == name => words.map(str::parse).collect::<Result<_, _>>().ok(),
+                _ => None,
+            }
+            .filter(|weights: &Vec<f32>| weights.len() == count)
+            .ok_or_else(|| invalid("the evaluator has invalid weights"))?;
+            Ok::<_, io::Error>(weights)
+        };
+        let value_weights = weights("value", value_feature_count::<G>())?;
+        let policy_weights = weights("policy", policy_feature_count::<G>())?;
+        Ok(Self {
+            rules,
+            value_weights,
+            policy_weights,
+            geometry: PhantomData,
+        })
+    }
+
+    /// The chance that the player to move wins, where a draw counts as half a win
+    pub(crate) fn value(&self, position: &Position<G>) -> f32 {
+        sigmoid(dot(&self.value_weights, &self.value_features(position)))
+    }
+
+    /// The priors of the moves of the player to move, which add up to 1
+    pub(crate) fn policy(&self, position: &Position<G>, moves: &[Move]) -> Vec<f32> {
+        let scores: Vec<f32> = self
+            .policy_features(position, moves)
+            .iter()
+            .map(|features| dot(&self.policy_weights, features))
+            .collect();
+        softmax(&scores)
+    }
+
+    fn value_features(&self, position: &Position<G>) -> Vec<f32> {
+        let threats = self.threats(position);
+        let free_pieces = position.free_pieces();
+        let mut features = vec![
+            1.0,
+            position.get_chosen_piece().map_or(0.0, |piece| {
+                (threats & piece_values::<G>(piece) != 0) as u32 as f32
+            }),
+            threats.count_ones() as f32 / (2 * G::ATTRIBUTES) as f32,
+            unsafe_share::<G>(threats, &free_pieces),
+        ];
+        let empty = position.free_spots().len();
+        features.push(1.0 - empty as f32 / G::SPOTS as f32);
+        features.push((empty % 2) as f32);
+        features.extend((0..G::SPOTS).map(|s| position.get_piece(Spot(s)).is_some() as u32 as f32));
+        features
+    }
+
+    fn policy_features(&self, position: &Position<G>, moves: &[Move]) -> Vec<Vec<f32>> {
+        let chosen = position.get_chosen_piece();
+        let free_pieces = position.free_pieces();
+        // the board after each placement, with its threats
+        let mut placed: Vec<(Option<Spot>, bool, u32)> = vec![];
+        moves
+            .iter()
+            .map(|mv| {
+                let (quarto, threats) = match placed.iter().find(|(spot, _, _)| *spot == mv.spot) {
+                    Some((_, quarto, threats)) => (*quarto, *threats),
+                    None => {
+                        let mut after = position.clone();
+                        if let (Some(spot), Some(piece)) = (mv.spot, chosen) {
+                            after.place_piece(spot, piece);
+                        }
+                        let quarto = mv.spot.map_or(false, |spot| after.is_quarto_at(spot));
+                        let threats = self.threats(&after);
+                        placed.push((mv.spot, quarto, threats));
+                        (quarto, threats)
+                    }
+                };
+                let given = mv.piece.map_or(0, piece_values::<G>);
+                let left: Vec<Piece> = free_pieces
+                    .iter()
+                    .copied()
+                    .filter(|piece| Some(*piece) != mv.piece)
+                    .collect();
+                let mut features = vec![
+                    (quarto && mv.piece.is_none()) as u32 as f32,
+                    (!quarto && mv.piece.is_none()) as u32 as f32,
+                    (threats & given != 0) as u32 as f32,
+                    threats.count_ones() as f32 / (2 * G::ATTRIBUTES) as f32,
+                    unsafe_share::<G>(threats, &left),
+                ];
+                features.extend((0..G::SPOTS).map(|s| (mv.spot == Some(Spot(s))) as u32 as f32));
+                features
+            })
+            .collect()
+    }
+
+    /// The attribute values that would complete a quarto in a group that lacks one piece,
+    /// with a bit for each attribute and value, as in `piece_values`
+    fn threats(&self, position: &Position<G>) -> u32 {
+        let attribute_masks = G::attribute_masks();
+        let mut threats = 0;
+        for &group_mask in G::group_masks(self.rules.squares) {
+            let size = (group_mask & attribute_masks[0]).count_ones();
+            let occupied = (position.board_mask & group_mask & attribute_masks[0]).count_ones();
+            if occupied + 1 != size {
+                continue;
+            }
+            for (a, &attribute_mask) in attribute_masks.iter().enumerate() {
+                let ones = (position.board_pieces & group_mask & attribute_mask).count_ones();
+                if ones == occupied {
+                    threats |= 1 << (2 * a + 1);
+                } else if ones == 0 {
+                    threats |= 1 << (2 * a);
+                }
+            }
+        }
+        threats
+    }
+}
+
+/// The values of the attributes of a piece, with the bit `2 * a + v` for value `v` of
+/// attribute `a`
+fn piece_values<G: Geometry>(piece: Piece) -> u32 {
+    (0..G::ATTRIBUTES as u32).fold(0, |values, a| {
+        values | 1 << (2 * a + (piece.0 as u32 >> a & 1))
+    })
+}
+
+/// The share of `pieces` that would complete one of the `threats`
+fn unsafe_share<G: Geometry>(threats: u32, pieces: &[Piece]) -> f32 {
+    let count = pieces
+        .iter()
+        .filter(|piece| threats & piece_values::<G>(**piece) != 0)
+        .count();
+    count as f32 / G::PIECES as f32
+}
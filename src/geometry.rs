@@ -0,0 +1,270 @@
+//! Dimensions of the board and the pieces
+//!
+//! The standard game has a 4×4 board and 16 pieces with 4 binary attributes. Other geometries
+//! are meant for research, e.g. a 3×3 board with 3 attributes can be solved in seconds.
+//! Each geometry is a separate type, so the code is compiled with its sizes as constants,
+//! and the standard game is as fast as if they were hard-coded.
+//!
+//! A board stores the pieces as a bitboard with `ATTRIBUTES` bits per spot, in row-major order.
+
+use crate::rules::Squares;
+use std::{
+    fmt::{self, Debug},
+    ops::{BitAnd, BitOr, BitOrAssign, Not, Shl, Shr},
+};
+
+/// A board with `SIDE`×`SIDE` spots, and pieces with `ATTRIBUTES` binary attributes
+#[derive(Debug, Clone, Copy)]
+pub struct Dims<const SIDE: usize, const ATTRIBUTES: usize>;
+
+pub trait Geometry: Debug + Clone + Copy + Send + Sync + 'static {
+    /// Number of spots along each side of the board
+    const SIDE: i8;
+    /// Number of binary attributes of a piece
+    const ATTRIBUTES: i8;
+    /// The board as it's selected in the user interface
+    const BOARD: Board;
+    const SPOTS: i8 = Self::SIDE * Self::SIDE;
+    const PIECES: i8 = 1 << Self::ATTRIBUTES;
+    /// The turn on which the last piece is placed, since either the board is full
+    /// or no pieces are left
+    const LAST_TURN: i8 = if Self::SPOTS < Self::PIECES {
+        Self::SPOTS
+    } else {
+        Self::PIECES
+    };
+
+    /// Big enough for the whole board
+    type Bits: BoardBits;
+    /// An array of `PIECES` elements
+    type Pieces: Permutation;
+    /// An array of `SPOTS` elements
+    type Spots: Permutation;
+
+    /// The bits of each attribute across the whole board
+    fn attribute_masks() -> &'static [Self::Bits];
+
+    /// The groups of spots that make a quarto, as masks of the spots' bits
+    fn group_masks(squares: Squares) -> &'static [Self::Bits];
+}
+
+pub trait BoardBits:
+    Copy
+    + Eq
+    + Debug
+    + Send
+    + Sync
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitOrAssign
+    + Not<Output = Self>
+    + Shl<i8, Output = Self>
+    + Shr<i8, Output = Self>
+    + fmt::LowerHex
+{
+    const ZERO: Self;
+
+    fn from_i8(value: i8) -> Self;
+
+    /// The lowest bits, truncated to an `i8`
+    fn to_i8(self) -> i8;
+
+    /// The number of set bits
+    fn count_ones(self) -> u32;
+}
+
+impl BoardBits for u64 {
+    const ZERO: Self = 0;
+
+    fn from_i8(value: i8) -> Self {
+        value as u64
+    }
+
+    fn to_i8(self) -> i8 {
+        self as i8
+    }
+
+    fn count_ones(self) -> u32 {
+        self.count_ones()
+    }
+}
+
+impl BoardBits for u128 {
+    const ZERO: Self = 0;
+
+    fn from_i8(value: i8) -> Self {
+        value as u128
+    }
+
+    fn to_i8(self) -> i8 {
+        self as i8
+    }
+
+    fn count_ones(self) -> u32 {
+        self.count_ones()
+    }
+}
+
+/// Pieces or spots in the order they're used during a game
+pub trait Permutation: Copy + Debug + Send + Sync + Default + AsRef<[i8]> + AsMut<[i8]> {}
+
+impl<T: Copy + Debug + Send + Sync + Default + AsRef<[i8]> + AsMut<[i8]>> Permutation for T {}
+
+macro_rules! geometry {
+    ($side:literal, $attributes:literal, $bits:tt, $board:ident) => {
+        impl Geometry for Dims<$side, $attributes> {
+            const SIDE: i8 = $side;
+            const ATTRIBUTES: i8 = $attributes;
+            const BOARD: Board = Board::$board;
+
+            type Bits = $bits;
+            type Pieces = [i8; 1 << $attributes];
+            type Spots = [i8; $side * $side];
+
+            fn attribute_masks() -> &'static [$bits] {
+                const MASKS: [$bits; $attributes] =
+                    geometry!(@convert $bits, attribute_masks($side, $attributes));
+                &MASKS
+            }
+
+            fn group_masks(squares: Squares) -> &'static [$bits] {
+                const LINES: [$bits; 2 * $side + 2] =
+                    geometry!(@convert $bits, group_masks($side, $attributes, Squares::Off));
+                const SQUARES: [$bits; 2 * $side + 2 + ($side - 1) * ($side - 1)] =
+                    geometry!(@convert $bits, group_masks($side, $attributes, Squares::On));
+                const TORUS: [$bits; 2 * $side + 2 + $side * $side] =
+                    geometry!(@convert $bits, group_masks($side, $attributes, Squares::Torus));
+                match squares {
+                    Squares::Off => &LINES,
+                    Squares::On => &SQUARES,
+                    Squares::Torus => &TORUS,
+                }
+            }
+        }
+    };
+    (@convert u64, $masks:expr) => {
+        narrow($masks)
+    };
+    (@convert u128, $masks:expr) => {
+        $masks
+    };
+}
+
+geometry!(3, 3, u64, Small);
+geometry!(4, 4, u64, Standard);
+geometry!(5, 5, u128, Large);
+
+/// The geometries that can be played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    /// 3×3 with 3 attributes
+    Small,
+    /// 4×4 with 4 attributes
+    Standard,
+    /// 5×5 with 5 attributes
+    Large,
+}
+
+impl Board {
+    /// Parse a board of the form `<SIDE>x<SIDE>x<ATTRIBUTES>`, e.g. `4x4x4`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "3x3x3" => Some(Board::Small),
+            "4x4x4" => Some(Board::Standard),
+            "5x5x5" => Some(Board::Large),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Board::Small => "3x3x3",
+            Board::Standard => "4x4x4",
+            Board::Large => "5x5x5",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The bits of the spot at `row` and `col`, which wrap around the edges of the board
+const fn spot_mask(side: usize, stride: usize, row: usize, col: usize) -> u128 {
+    ((1 << stride) - 1) << (stride * ((row % side) * side + (col % side)))
+}
+
+const fn attribute_masks<const N: usize>(side: usize, stride: usize) -> [u128; N] {
+    let mut masks = [0; N];
+    let mut spot = 0;
+    while spot < side * side {
+        let mut attribute = 0;
+        while attribute < N {
+            masks[attribute] |= 1 << (stride * spot + attribute);
+            attribute += 1;
+        }
+        spot += 1;
+    }
+    masks
+}
+
+/// List the rows, columns and diagonals, followed by the 2×2 squares if they are enabled
+const fn group_masks<const N: usize>(side: usize, stride: usize, squares: Squares) -> [u128; N] {
+    let mut masks = [0; N];
+    let mut i = 0;
+    // rows
+    while i < side {
+        let mut col = 0;
+        while col < side {
+            masks[i] |= spot_mask(side, stride, i, col);
+            col += 1;
+        }
+        i += 1;
+    }
+    // columns
+    while i < 2 * side {
+        let mut row = 0;
+        while row < side {
+            masks[i] |= spot_mask(side, stride, row, i - side);
+            row += 1;
+        }
+        i += 1;
+    }
+    // diagonals
+    let mut k = 0;
+    while k < side {
+        masks[i] |= spot_mask(side, stride, k, k);
+        masks[i + 1] |= spot_mask(side, stride, k, side - 1 - k);
+        k += 1;
+    }
+    i += 2;
+    // number of squares along each side of the board
+    let square_count = match squares {
+        Squares::Off => 0,
+        Squares::On => side - 1,
+        Squares::Torus => side,
+    };
+    let mut row = 0;
+    while row < square_count {
+        let mut col = 0;
+        while col < square_count {
+            masks[i] = spot_mask(side, stride, row, col)
+                | spot_mask(side, stride, row, col + 1)
+                | spot_mask(side, stride, row + 1, col)
+                | spot_mask(side, stride, row + 1, col + 1);
+            i += 1;
+            col += 1;
+        }
+        row += 1;
+    }
+    masks
+}
+
+const fn narrow<const N: usize>(masks: [u128; N]) -> [u64; N] {
+    let mut narrow = [0; N];
+    let mut i = 0;
+    while i < N {
+        narrow[i] = masks[i] as u64;
+        i += 1;
+    }
+    narrow
+}
@@ -1,94 +1,662 @@
+mod args;
+mod book;
+mod clock;
+mod evaluator;
+mod geometry;
+mod rules;
+mod selection;
+mod selfplay;
+mod solver;
+mod symmetry;
+mod tablebase;
+
+use book::Book;
+use clock::{Clock, TimeControl};
+use evaluator::Evaluator;
+use geometry::{Board, BoardBits, Dims, Geometry, Permutation};
+use rules::Rules;
+use selection::{
+    NodeStats, Puct, Selection, SelectionPolicy, Thompson, Ucb1, Ucb1Tuned, SELECTION_NAMES,
+};
 use std::{
     io::{self, Write},
+    marker::PhantomData,
     num::Wrapping,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
+use tablebase::Tablebase;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let input = std::io::stdin();
-    let mut output = std::io::stdout();
-
-    let mut engine: Box<dyn Engine> = Box::new(Bruto::new());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("solve") => return solver::run(&args[1..], &mut std::io::stdout()),
+        Some("tablebase") => return tablebase::run(&args[1..], &mut std::io::stdout()),
+        Some("book") => return book::run(&args[1..], &mut std::io::stdout()),
+        Some("selfplay") => return selfplay::run(&args[1..], &mut std::io::stdout()),
+        Some("train") => return evaluator::run(&args[1..], &mut std::io::stdout()),
+        _ => {}
+    }
 
-    let mut history = {
-        let mut pcg = Pcg::new_from_time();
-        random_history(pcg.rand_16_fact(), pcg.rand_16_fact())
+    let mut settings = Settings {
+        rules: Rules::standard(),
+        engine: "bruto".to_string(),
+        human_turn_parity: 0,
+        pondering: false,
+        show_info: false,
+        time_control: None,
+        stats_path: None,
     };
+    let mut board = Some(Board::Standard);
+    while let Some(next) = board {
+        board = match next {
+            Board::Small => run_session::<Dims<3, 3>>(&mut settings)?,
+            Board::Standard => run_session::<Dims<4, 4>>(&mut settings)?,
+            Board::Large => run_session::<Dims<5, 5>>(&mut settings)?,
+        };
+    }
+    Ok(())
+}
+
+/// The choices of the player that carry over to a game on another board
+struct Settings {
+    rules: Rules,
+    engine: String,
+    human_turn_parity: i8,
+    pondering: bool,
+    show_info: bool,
+    time_control: Option<TimeControl>,
+    /// File that the statistics of the engine's searches are appended to
+    stats_path: Option<String>,
+}
+
+/// Play a game on a board of geometry `G`
+///
+/// Returns the board to play on next, if the player selected one before the first move.
+fn run_session<G: Geometry>(
+    settings: &mut Settings,
+) -> Result<Option<Board>, Box<dyn std::error::Error>> {
+    let input = std::io::stdin();
+    let mut output = std::io::stdout();
 
-    let mut turn = 0;
-    let mut human_turn_parity = 0;
-    while turn <= 16 {
-        let mv = if turn & 1 == human_turn_parity {
+    let mut engine = new_engine::<G>(&settings.engine).unwrap();
+    let mut tablebase: Option<Arc<Tablebase<G>>> = None;
+    let mut book: Option<Arc<Book<G>>> = None;
+    let mut evaluator: Option<Arc<Evaluator<G>>> = None;
+
+    let mut game = Game::new(random_history::<G>(
+        settings.rules,
+        &mut Pcg::new_from_time(),
+    ));
+    let mut clock = settings.time_control.map(Clock::new);
+
+    // when the player to move started their turn
+    let mut turn_start = Instant::now();
+    while game.outcome() == GameResult::InProgress {
+        let player = game.side_to_move();
+        let mv = if player as i8 == settings.human_turn_parity {
             // ask human for next move
             write!(output, "player> ")?;
             output.flush()?;
+            // let the engine think on the human's time until the input arrives
+            let stop = AtomicBool::new(false);
             let mut input_line = String::new();
-            let _count = input.read_line(&mut input_line)?;
+            let count = thread::scope(|scope| {
+                if settings.pondering {
+                    scope.spawn(|| {
+                        engine.ponder(&game, &mut SearchControl::new(&stop));
+                    });
+                }
+                let count = input.read_line(&mut input_line);
+                stop.store(true, Ordering::Relaxed);
+                count
+            })?;
+            if count == 0 {
+                // end of input
+                break;
+            }
+            if let Some(clock) = &clock {
+                if game.turn() > 0 && turn_start.elapsed() > clock.time_left(player) {
+                    game.end(GameResult::Timeout(player));
+                    writeln!(output, "{}", game.outcome())?;
+                    break;
+                }
+            }
+            if let Some(spec) = input_line.strip_prefix("play ") {
+                match new_engine(spec.trim()) {
+                    Some(new) => {
+                        engine = new;
+                        if let Some(tablebase) = &tablebase {
+                            engine.set_tablebase(tablebase.clone());
+                        }
+                        if let Some(book) = &book {
+                            engine.set_book(book.clone());
+                        }
+                        if let Some(evaluator) = &evaluator {
+                            engine.set_evaluator(evaluator.clone());
+                        }
+                        settings.engine = spec.trim().to_string();
+                    }
+                    None => writeln!(output, "ERROR: unknown engine `{}`", spec.trim())?,
+                }
+                continue;
+            }
+            if let Some(args) = input_line.strip_prefix("match ") {
+                match args.split_whitespace().collect::<Vec<_>>()[..] {
+                    [spec_0, spec_1, games] => match games.parse() {
+                        Ok(games) => {
+                            run_match::<G>([spec_0, spec_1], games, game.rules(), &mut output)?
+                        }
+                        Err(_) => writeln!(output, "ERROR: invalid number of games")?,
+                    },
+                    _ => writeln!(output, "ERROR: usage: match <engine> <engine> <games>")?,
+                }
+                continue;
+            }
+            if let Some(games) = input_line.strip_prefix("ladder ") {
+                match games.trim().parse() {
+                    Ok(games) => run_ladder::<G>(games, game.rules(), &mut output)?,
+                    Err(_) => writeln!(output, "ERROR: invalid number of games")?,
+                }
+                continue;
+            }
+            if let Some(name) = input_line.strip_prefix("rules ") {
+                match Rules::parse(name.trim()) {
+                    Some(rules) => match game.set_rules(rules) {
+                        Ok(()) => settings.rules = rules,
+                        Err(()) => writeln!(
+                            output,
+                            "ERROR: rules can only be changed before the first move"
+                        )?,
+                    },
+                    None => writeln!(output, "ERROR: unknown rules `{}`", name.trim())?,
+                }
+                continue;
+            }
+            if let Some(name) = input_line.strip_prefix("board ") {
+                match Board::parse(name.trim()) {
+                    Some(_) if game.turn() > 0 => writeln!(
+                        output,
+                        "ERROR: the board can only be changed before the first move"
+                    )?,
+                    Some(board) => return Ok(Some(board)),
+                    None => writeln!(output, "ERROR: unknown board `{}`", name.trim())?,
+                }
+                continue;
+            }
+            if let Some(path) = input_line.strip_prefix("tablebase ") {
+                match Tablebase::load(path.trim()) {
+                    Ok(loaded) => {
+                        let loaded = Arc::new(loaded);
+                        engine.set_tablebase(loaded.clone());
+                        tablebase = Some(loaded);
+                    }
+                    Err(err) => writeln!(output, "ERROR: {}", err)?,
+                }
+                continue;
+            }
+            if let Some(spec) = input_line.strip_prefix("clock ") {
+                match (spec.trim(), TimeControl::parse(spec.trim())) {
+                    _ if game.turn() > 0 => writeln!(
+                        output,
+                        "ERROR: the clock can only be set before the first move"
+                    )?,
+                    ("off", _) => {
+                        clock = None;
+                        settings.time_control = None;
+                    }
+                    (_, Some(control)) => {
+                        clock = Some(Clock::new(control));
+                        settings.time_control = Some(control);
+                    }
+                    (_, None) => writeln!(output, "ERROR: invalid time control `{}`", spec.trim())?,
+                }
+                continue;
+            }
+            if let Some(path) = input_line.strip_prefix("book ") {
+                match Book::load(path.trim()) {
+                    Ok(loaded) => {
+                        let loaded = Arc::new(loaded);
+                        engine.set_book(loaded.clone());
+                        book = Some(loaded);
+                    }
+                    Err(err) => writeln!(output, "ERROR: {}", err)?,
+                }
+                continue;
+            }
+            if let Some(path) = input_line.strip_prefix("evaluator ") {
+                match Evaluator::load(path.trim()) {
+                    Ok(loaded) => {
+                        let loaded = Arc::new(loaded);
+                        engine.set_evaluator(loaded.clone());
+                        evaluator = Some(loaded);
+                    }
+                    Err(err) => writeln!(output, "ERROR: {}", err)?,
+                }
+                continue;
+            }
+            if let Some(path) = input_line.strip_prefix("stats ") {
+                settings.stats_path = match path.trim() {
+                    "off" => None,
+                    path => Some(path.to_string()),
+                };
+                continue;
+            }
+            if let Some(args) = input_line.strip_prefix("tree ") {
+                let args: Vec<&str> = args.split_whitespace().collect();
+                let parsed = args
+                    .split_first()
+                    .and_then(|(path, filters)| Some((*path, TreeFilter::parse(filters)?)));
+                match parsed {
+                    Some((path, filter)) => match engine.print_tree(&filter) {
+                        Some(tree) => {
+                            if let Err(err) = std::fs::write(path, tree) {
+                                writeln!(output, "ERROR: {}", err)?;
+                            }
+                        }
+                        None => {
+                            writeln!(output, "ERROR: {} has no search tree", engine.get_name())?
+                        }
+                    },
+                    None => writeln!(
+                        output,
+                        "ERROR: usage: tree <file> [depth <n>] [visits <n>] [top <n>]"
+                    )?,
+                }
+                continue;
+            }
+            if let Some(depth) = input_line.strip_prefix("perft ") {
+                match depth.trim().parse() {
+                    Ok(depth) => {
+                        let start = Instant::now();
+                        writeln!(output, "nodes {}", perft(&mut game.clone(), depth))?;
+                        writeln!(output, "time {:.3}s", start.elapsed().as_secs_f32())?;
+                    }
+                    Err(_) => writeln!(output, "ERROR: invalid depth")?,
+                }
+                continue;
+            }
+            if let Some(args) = input_line.strip_prefix("set ") {
+                if let Err(err) = set_engine_option(engine.as_mut(), args) {
+                    writeln!(output, "ERROR: {}", err)?;
+                }
+                continue;
+            }
             match input_line.as_ref() {
                 "exit\n" => {
                     break;
                 }
-                "swap\n" => {
-                    human_turn_parity ^= 1;
+                "ponder on\n" => {
+                    settings.pondering = true;
+                    continue;
+                }
+                "ponder off\n" => {
+                    settings.pondering = false;
+                    continue;
+                }
+                "info on\n" => {
+                    settings.show_info = true;
+                    continue;
+                }
+                "info off\n" => {
+                    settings.show_info = false;
+                    continue;
+                }
+                "rules\n" => {
+                    writeln!(output, "{}", game.rules())?;
+                    continue;
+                }
+                "board\n" => {
+                    writeln!(output, "{}", G::BOARD)?;
+                    continue;
+                }
+                "clock\n" => {
+                    match &clock {
+                        Some(clock) => writeln!(output, "{}", clock)?,
+                        None => writeln!(output, "clock off")?,
+                    }
                     continue;
                 }
-                "play bruto\n" => {
-                    engine = Box::new(Bruto::new());
+                "options\n" => {
+                    for option in engine.get_options() {
+                        writeln!(output, "{}.{}", engine.get_name(), option)?;
+                    }
                     continue;
                 }
-                "play rando\n" => {
-                    engine = Box::new(Rando::new());
+                "swap\n" => {
+                    settings.human_turn_parity ^= 1;
+                    continue;
+                }
+                "resign\n" => {
+                    game.end(GameResult::Resignation(player));
+                    writeln!(output, "{}", game.outcome())?;
+                    break;
+                }
+                "draw\n" => {
+                    if engine.accept_draw(&game) {
+                        writeln!(output, "{} accepts the draw", engine.get_name())?;
+                        game.end(GameResult::Draw);
+                        writeln!(output, "{}", game.outcome())?;
+                        break;
+                    }
+                    writeln!(output, "{} declines the draw", engine.get_name())?;
                     continue;
                 }
                 _ => {}
             }
-            match parse_move(&input_line) {
+            match parse_move::<G>(&input_line) {
                 Ok(mv) => mv,
                 Err(err) => {
-                    writeln!(output, "ERROR: {:?}", err)?;
+                    writeln!(output, "ERROR: {}", err)?;
                     continue;
                 }
             }
         } else {
             // ask engine for next move
-            write!(output, "{}> ", engine.get_name())?;
-            output.flush()?;
-            let mv = engine.play(&history, turn);
+            let stop = AtomicBool::new(false);
+            let time_left = clock
+                .as_ref()
+                .filter(|_| game.turn() > 0)
+                .map(|clock| (clock.control, clock.time_left(player)));
+            let action = if settings.show_info {
+                // print progress reports before the move
+                let mut written = Ok(());
+                let mut print_info = |info: &SearchInfo| {
+                    if written.is_ok() {
+                        written = writeln!(output, "info {}", print_info::<G>(info));
+                    }
+                };
+                let mut control = SearchControl::new(&stop)
+                    .with_time(time_left)
+                    .with_info(&mut print_info);
+                let action = engine.play(&game, &mut control);
+                written?;
+                write!(output, "{}> ", engine.get_name())?;
+                action
+            } else {
+                write!(output, "{}> ", engine.get_name())?;
+                output.flush()?;
+                let mut control = SearchControl::new(&stop).with_time(time_left);
+                engine.play(&game, &mut control)
+            };
+            if let (Some(path), Some(stats)) = (&settings.stats_path, engine.search_stats()) {
+                let record = print_stats::<G>(engine.get_name(), stats);
+                if let Err(err) = append_line(path, &record) {
+                    writeln!(output, "ERROR: {}", err)?;
+                }
+            }
+            let mv = match action {
+                Action::Move(mv) => mv,
+                Action::Resign => {
+                    writeln!(output, "resign")?;
+                    game.end(GameResult::Resignation(player));
+                    writeln!(output, "{}", game.outcome())?;
+                    break;
+                }
+            };
             // print move
-            writeln!(output, "{}", print_move(&mv))?;
+            writeln!(output, "{}", print_move::<G>(&mv))?;
             mv
         };
-        let result = history.try_move(turn, &mv);
-        match result {
+        let first_move = game.turn() == 0;
+        match game.apply(&mv) {
             Ok(_) => {
+                if let Some(clock) = clock.as_mut().filter(|_| !first_move) {
+                    if !clock.charge(player, turn_start.elapsed()) {
+                        game.end(GameResult::Timeout(player));
+                        writeln!(output, "{}", game.outcome())?;
+                        break;
+                    }
+                }
+                turn_start = Instant::now();
                 // print board after move
-                turn += 1;
-                let position = history.get_position(turn);
                 writeln!(output)?;
-                position.print(&mut output)?;
+                game.position().print(&mut output)?;
                 writeln!(output)?;
-                if !position.is_quarto() {
-                    history.print_free_pieces(turn, &mut output)?;
+                if game.outcome() == GameResult::InProgress {
+                    game.history().print_free_pieces(game.turn(), &mut output)?;
                     writeln!(output)?;
-                }
-                if position.is_quarto() {
-                    break;
+                    if let Some(clock) = &clock {
+                        writeln!(output, "{}", clock)?;
+                    }
+                } else {
+                    writeln!(output, "{}", game.outcome())?;
                 }
             }
-            Err(_) => {
-                writeln!(output, "ERROR: illegal move")?;
+            Err(err) if player as i8 == settings.human_turn_parity => {
+                writeln!(output, "ERROR: illegal move, {}", err)?;
+            }
+            Err(err) => {
+                // an engine that makes an illegal move loses the game
+                writeln!(
+                    output,
+                    "ERROR: illegal move by {}, {}",
+                    engine.get_name(),
+                    err
+                )?;
+                game.end(GameResult::Win(player ^ 1));
+                writeln!(output, "{}", game.outcome())?;
             }
         }
     }
 
-    Ok(())
+    Ok(None)
 }
 
-#[derive(Debug)]
+/// Create an engine by name, where Bruto can be weakened with a suffix `:<level>` for levels 1..10
+///
+/// The name can be followed by options of the form `,<option>=<value>`, e.g.
+/// `bruto,selection=thompson`.
+fn new_engine<G: Geometry>(spec: &str) -> Option<Box<dyn Engine<G>>> {
+    let mut parts = spec.split(',');
+    let mut engine = new_named_engine(parts.next()?)?;
+    for part in parts {
+        let (name, value) = part.split_once('=')?;
+        set_named_option(engine.as_mut(), name, value).ok()?;
+    }
+    Some(engine)
+}
+
+fn new_named_engine<G: Geometry>(spec: &str) -> Option<Box<dyn Engine<G>>> {
+    match spec.split_once(':') {
+        None => match spec {
+            "bruto" => Some(Box::new(Bruto::<G>::new())),
+            "rando" => Some(Box::new(Rando::new())),
+            "greedy" => Some(Box::new(Greedy::new())),
+            _ => None,
+        },
+        Some(("bruto", level)) => {
+            let bruto = Bruto::<G>::with_level(level.parse().ok()?)?;
+            Some(Box::new(bruto))
+        }
+        _ => None,
+    }
+}
+
+/// Play a game between two engines, where `engines[0]` moves first
+///
+/// An illegal move loses the game.
+fn play_game<G: Geometry>(engines: [&mut dyn Engine<G>; 2], mut game: Game<G>) -> GameResult {
+    let stop = AtomicBool::new(false);
+    while game.outcome() == GameResult::InProgress {
+        let player = game.side_to_move();
+        match engines[player].play(&game, &mut SearchControl::new(&stop)) {
+            Action::Move(mv) => {
+                if game.apply(&mv).is_err() {
+                    game.end(GameResult::Win(player ^ 1));
+                }
+            }
+            Action::Resign => game.end(GameResult::Resignation(player)),
+        }
+    }
+    game.outcome()
+}
+
+/// Who won a game, if it's over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GameResult {
+    InProgress,
+    /// A win for the player with this index, where player 0 moves first
+    Win(usize),
+    Draw,
+    /// The player with this index resigned
+    Resignation(usize),
+    /// The player with this index ran out of time
+    Timeout(usize),
+}
+
+impl GameResult {
+    /// The index of the winner, or None for a draw or a game in progress
+    fn winner(&self) -> Option<usize> {
+        match self {
+            GameResult::Win(winner) => Some(*winner),
+            GameResult::Resignation(loser) | GameResult::Timeout(loser) => Some(loser ^ 1),
+            GameResult::InProgress | GameResult::Draw => None,
+        }
+    }
+}
+
+impl std::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GameResult::InProgress => write!(f, "game in progress"),
+            GameResult::Win(winner) => write!(f, "player {} wins", winner + 1),
+            GameResult::Draw => write!(f, "draw"),
+            GameResult::Resignation(loser) => write!(
+                f,
+                "player {} resigns, player {} wins",
+                loser + 1,
+                (loser ^ 1) + 1
+            ),
+            GameResult::Timeout(loser) => write!(
+                f,
+                "player {} runs out of time, player {} wins",
+                loser + 1,
+                (loser ^ 1) + 1
+            ),
+        }
+    }
+}
+
+/// Wins, draws and losses of the first engine in a match
+#[derive(Debug, Default)]
+struct MatchScore {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl MatchScore {
+    /// Points scored, counting a draw as half a win
+    fn points(&self) -> f32 {
+        self.wins as f32 + 0.5 * self.draws as f32
+    }
+
+    /// Whether the first engine did clearly better than the second, by more than half the
+    /// points and by at least the ladder's margin
+    fn is_clear_win(&self) -> bool {
+        let games = (self.wins + self.draws + self.losses) as f32;
+        self.points() > 0.5 * games && self.points() >= (0.5 + LADDER_MARGIN) * games
+    }
+}
+
+/// Play a number of games between two engines, taking turns to move first
+fn play_match<G: Geometry>(specs: [&str; 2], games: u32, rules: Rules) -> Option<MatchScore> {
+    let mut engines = [new_engine::<G>(specs[0])?, new_engine::<G>(specs[1])?];
+    let mut pcg = Pcg::new_from_time();
+    let mut score = MatchScore::default();
+    for game in 0..games {
+        let start = Game::new(random_history::<G>(rules, &mut pcg));
+        let first = game as usize & 1;
+        let [engine_0, engine_1] = &mut engines;
+        let result = if first == 0 {
+            play_game([engine_0.as_mut(), engine_1.as_mut()], start)
+        } else {
+            play_game([engine_1.as_mut(), engine_0.as_mut()], start)
+        };
+        match result.winner().map(|winner| winner ^ first) {
+            Some(0) => score.wins += 1,
+            Some(_) => score.losses += 1,
+            None => score.draws += 1,
+        }
+    }
+    Some(score)
+}
+
+fn run_match<G: Geometry>(
+    specs: [&str; 2],
+    games: u32,
+    rules: Rules,
+    output: &mut dyn io::Write,
+) -> Result<(), io::Error> {
+    match play_match::<G>(specs, games, rules) {
+        Some(score) => writeln!(
+            output,
+            "{} vs {}: {} wins, {} draws, {} losses",
+            specs[0], specs[1], score.wins, score.draws, score.losses
+        ),
+        None => writeln!(output, "ERROR: unknown engine"),
+    }
+}
+
+/// Share of the points that a Bruto level needs beyond half of them, against the level below, to
+/// count as stronger
+const LADDER_MARGIN: f32 = 0.05;
+
+/// Play each Bruto level against the level below it, to check that skill goes up with the level
+fn run_ladder<G: Geometry>(
+    games: u32,
+    rules: Rules,
+    output: &mut dyn io::Write,
+) -> Result<(), io::Error> {
+    let mut monotonic = true;
+    for level in 1..10 {
+        let specs = [format!("bruto:{}", level + 1), format!("bruto:{}", level)];
+        let score = play_match::<G>([&specs[0], &specs[1]], games, rules).unwrap();
+        let ok = score.is_clear_win();
+        monotonic &= ok;
+        writeln!(
+            output,
+            "{} vs {}: {} wins, {} draws, {} losses{}",
+            specs[0],
+            specs[1],
+            score.wins,
+            score.draws,
+            score.losses,
+            if ok {
+                ""
+            } else {
+                " (not stronger than the level below)"
+            }
+        )?;
+    }
+    if monotonic {
+        writeln!(output, "skill goes up monotonically with the level")
+    } else {
+        writeln!(
+            output,
+            "ERROR: skill doesn't go up monotonically with the level"
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct Move {
     spot: Option<Spot>,
     piece: Option<Piece>,
 }
 
+/// What an engine does on its turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Move(Move),
+    Resign,
+}
+
 #[derive(Debug)]
 enum ParseError {
     InputTooShort,
@@ -99,53 +667,153 @@ enum ParseError {
     PropsMismatch,
 }
 
-fn parse_move(text: &str) -> Result<Move, ParseError> {
-    if text.len() < 6 {
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::InputTooShort => write!(f, "the move is too short"),
+            ParseError::InvalidRow => write!(f, "invalid row"),
+            ParseError::InvalidColumn => write!(f, "invalid column"),
+            ParseError::RowColumnMismatch => {
+                write!(
+                    f,
+                    "the row and the column have to be both given or both `.`"
+                )
+            }
+            ParseError::InvalidProp => write!(f, "invalid property, expected `o`, `x` or `.`"),
+            ParseError::PropsMismatch => {
+                write!(f, "the properties have to be all given or all `.`")
+            }
+        }
+    }
+}
+
+/// Why a move is illegal, with the spot or piece at fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MoveErrorKind {
+    SpotOccupied(Spot),
+    PieceUsed(Piece),
+    /// A spot was given on the first move, when there is no piece to place yet
+    SpotOnFirstTurn(Spot),
+    MissingSpot,
+    /// No piece was given after placing one on this spot, although the game goes on
+    MissingPiece(Option<Spot>),
+    /// A piece was given after completing a quarto, which ends the game
+    PieceAfterQuarto(Piece),
+    GameFinished,
+}
+
+/// An illegal move on the board of `G`, which writes its spots and pieces as moves do
+#[derive(Debug, Clone, Copy)]
+struct MoveError<G: Geometry> {
+    kind: MoveErrorKind,
+    geometry: PhantomData<G>,
+}
+
+impl<G: Geometry> From<MoveErrorKind> for MoveError<G> {
+    fn from(kind: MoveErrorKind) -> Self {
+        Self {
+            kind,
+            geometry: PhantomData,
+        }
+    }
+}
+
+impl<G: Geometry> std::fmt::Display for MoveError<G> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let spot_text = |spot: &Spot| spot_to_chars::<G>(spot).into_iter().collect::<String>();
+        let piece_text = |piece: &Piece| piece_to_chars::<G>(piece).into_iter().collect::<String>();
+        match &self.kind {
+            MoveErrorKind::SpotOccupied(spot) => {
+                write!(f, "spot {} is already occupied", spot_text(spot))
+            }
+            MoveErrorKind::PieceUsed(piece) => {
+                write!(f, "piece {} is already used", piece_text(piece))
+            }
+            MoveErrorKind::SpotOnFirstTurn(spot) => {
+                write!(
+                    f,
+                    "there is no piece to place on {} on the first move",
+                    spot_text(spot)
+                )
+            }
+            MoveErrorKind::MissingSpot => write!(f, "the chosen piece has to be placed"),
+            MoveErrorKind::MissingPiece(Some(spot)) => {
+                write!(
+                    f,
+                    "a piece has to be given after placing on {}",
+                    spot_text(spot)
+                )
+            }
+            MoveErrorKind::MissingPiece(None) => write!(f, "a piece has to be given"),
+            MoveErrorKind::PieceAfterQuarto(piece) => write!(
+                f,
+                "piece {} can't be given after completing a quarto",
+                piece_text(piece)
+            ),
+            MoveErrorKind::GameFinished => write!(f, "the game is already over"),
+        }
+    }
+}
+
+impl<G: Geometry> std::error::Error for MoveError<G> {}
+
+/// Parse a move of the form `RCPPPP`, with a piece of as many `P`s as the attributes of `G`
+fn parse_move<G: Geometry>(text: &str) -> Result<Move, ParseError> {
+    let chars: Vec<char> = text.chars().take(2 + G::ATTRIBUTES as usize).collect();
+    if chars.len() < 2 + G::ATTRIBUTES as usize {
         return Err(ParseError::InputTooShort);
     }
-    let chars: Vec<char> = text.chars().take(6).collect();
-    let spot = parse_spot(chars[0], chars[1])?;
-    let piece = parse_piece(chars[2..6].try_into().unwrap())?;
+    let spot = parse_spot::<G>(chars[0], chars[1])?;
+    let piece = parse_piece(&chars[2..])?;
     Ok(Move { spot, piece })
 }
 
-fn parse_spot(r: char, c: char) -> Result<Option<Spot>, ParseError> {
+fn parse_spot<G: Geometry>(r: char, c: char) -> Result<Option<Spot>, ParseError> {
     let row = match r {
-        'a' => Some(0),
-        'b' => Some(1),
-        'c' => Some(2),
-        'd' => Some(3),
         '.' => None,
-        _ => return Err(ParseError::InvalidRow),
+        _ => match ROW_SYMBOLS
+            .iter()
+            .take(G::SIDE as usize)
+            .position(|s| *s == r)
+        {
+            Some(row) => Some(row as i8),
+            None => return Err(ParseError::InvalidRow),
+        },
     };
     let col = match c {
-        '1' => Some(0),
-        '2' => Some(1),
-        '3' => Some(2),
-        '4' => Some(3),
         '.' => None,
-        _ => return Err(ParseError::InvalidColumn),
+        _ => match COL_SYMBOLS
+            .iter()
+            .take(G::SIDE as usize)
+            .position(|s| *s == c)
+        {
+            Some(col) => Some(col as i8),
+            None => return Err(ParseError::InvalidColumn),
+        },
     };
     match (row, col) {
-        (Some(row), Some(col)) => Ok(Some(Spot::from_row_col(row, col))),
+        (Some(row), Some(col)) => Ok(Some(Spot::from_row_col::<G>(row, col))),
         (None, None) => Ok(None),
         _ => Err(ParseError::RowColumnMismatch),
     }
 }
 
-fn parse_piece(p: &[char; 4]) -> Result<Option<Piece>, ParseError> {
-    match [
-        parse_prop(p[0])?,
-        parse_prop(p[1])?,
-        parse_prop(p[2])?,
-        parse_prop(p[3])?,
-    ] {
-        [Some(p0), Some(p1), Some(p2), Some(p3)] => {
-            Ok(Some(Piece(p0 | (p1 << 1) | (p2 << 2) | (p3 << 3))))
+fn parse_piece(p: &[char]) -> Result<Option<Piece>, ParseError> {
+    let props = p
+        .iter()
+        .map(|c| parse_prop(*c))
+        .collect::<Result<Vec<_>, _>>()?;
+    if props.iter().all(|prop| prop.is_none()) {
+        return Ok(None);
+    }
+    let mut piece = 0;
+    for (k, prop) in props.iter().enumerate() {
+        match prop {
+            Some(prop) => piece |= prop << k,
+            None => return Err(ParseError::PropsMismatch),
         }
-        [None, None, None, None] => Ok(None),
-        _ => Err(ParseError::PropsMismatch),
     }
+    Ok(Some(Piece(piece)))
 }
 
 fn parse_prop(p: char) -> Result<Option<i8>, ParseError> {
@@ -157,263 +825,1474 @@ fn parse_prop(p: char) -> Result<Option<i8>, ParseError> {
     }
 }
 
-fn print_move(mv: &Move) -> String {
-    option_spot_to_chars(&mv.spot)
-        .iter()
-        .chain(option_piece_to_chars(&mv.piece).iter())
-        .collect()
-}
+fn print_move<G: Geometry>(mv: &Move) -> String {
+    option_spot_to_chars::<G>(&mv.spot)
+        .iter()
+        .chain(option_piece_to_chars::<G>(&mv.piece).iter())
+        .collect()
+}
+
+trait Engine<G: Geometry>: Send {
+    fn get_name(&self) -> &'static str;
+
+    /// Pick a move or resign, returning early with the best move so far when the search is
+    /// stopped
+    fn play(&mut self, game: &Game<G>, control: &mut SearchControl) -> Action;
+
+    /// Think about the position while the opponent is to move, until the search is stopped
+    fn ponder(&mut self, _game: &Game<G>, _control: &mut SearchControl) {}
+
+    /// List the settings that can be changed with `set_option`, with their current values
+    fn get_options(&self) -> Vec<EngineOption> {
+        vec![]
+    }
+
+    /// Change a setting, given a value that was checked against the option's type and range
+    fn set_option(&mut self, name: &str, _value: OptionValue) -> Result<(), OptionError> {
+        Err(OptionError::UnknownOption(name.to_string()))
+    }
+
+    /// Look up the positions that are in the tablebase instead of searching them
+    fn set_tablebase(&mut self, _tablebase: Arc<Tablebase<G>>) {}
+
+    /// Play the moves of the opening book in the positions that are in it
+    fn set_book(&mut self, _book: Arc<Book<G>>) {}
+
+    /// Rate positions and moves with a learned evaluator
+    fn set_evaluator(&mut self, _evaluator: Arc<Evaluator<G>>) {}
+
+    /// Answer a draw offer of the opponent, who is to move
+    fn accept_draw(&mut self, _game: &Game<G>) -> bool {
+        false
+    }
+
+    /// Statistics of the search for the last move played, if it took a search
+    fn search_stats(&self) -> Option<&SearchStats> {
+        None
+    }
+
+    /// The tree of the last search in Graphviz DOT format, if the engine has one
+    fn print_tree(&self, _filter: &TreeFilter) -> Option<String> {
+        None
+    }
+}
+
+/// Which part of a search tree to export
+#[derive(Debug)]
+struct TreeFilter {
+    /// Leave out the nodes more than this many moves below the root
+    max_depth: usize,
+    /// Leave out the nodes that the search visited fewer times
+    min_visits: u32,
+    /// Keep only this many of the most visited children of each node
+    top: Option<usize>,
+}
+
+impl TreeFilter {
+    /// Parse filters of the form `[depth <N>] [visits <N>] [top <N>]`, in any order
+    fn parse(args: &[&str]) -> Option<Self> {
+        let mut filter = TreeFilter {
+            max_depth: 3,
+            min_visits: 1,
+            top: None,
+        };
+        for pair in args.chunks(2) {
+            match *pair {
+                ["depth", depth] => filter.max_depth = depth.parse().ok()?,
+                ["visits", visits] => filter.min_visits = visits.parse().ok()?,
+                ["top", top] => filter.top = Some(top.parse().ok()?),
+                _ => return None,
+            }
+        }
+        Some(filter)
+    }
+}
+
+/// A setting that changes how an engine plays
+#[derive(Debug, Clone)]
+struct EngineOption {
+    name: &'static str,
+    kind: OptionKind,
+}
+
+#[derive(Debug, Clone)]
+enum OptionKind {
+    Int {
+        value: i64,
+        default: i64,
+        min: i64,
+        max: i64,
+    },
+    Float {
+        value: f64,
+        default: f64,
+        min: f64,
+        max: f64,
+    },
+    Bool {
+        value: bool,
+        default: bool,
+    },
+    Choice {
+        value: &'static str,
+        default: &'static str,
+        choices: &'static [&'static str],
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OptionValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Choice(&'static str),
+}
+
+#[derive(Debug)]
+enum OptionError {
+    UnknownOption(String),
+    InvalidValue(String),
+    OutOfRange(String),
+}
+
+impl EngineOption {
+    /// Parse and check a value for this option
+    fn parse(&self, text: &str) -> Result<OptionValue, OptionError> {
+        let invalid = || OptionError::InvalidValue(text.to_string());
+        let out_of_range = || OptionError::OutOfRange(text.to_string());
+        match self.kind {
+            OptionKind::Int { min, max, .. } => {
+                let value = text.parse::<i64>().map_err(|_| invalid())?;
+                if !(min..=max).contains(&value) {
+                    return Err(out_of_range());
+                }
+                Ok(OptionValue::Int(value))
+            }
+            OptionKind::Float { min, max, .. } => {
+                let value = text.parse::<f64>().map_err(|_| invalid())?;
+                if !(min..=max).contains(&value) {
+                    return Err(out_of_range());
+                }
+                Ok(OptionValue::Float(value))
+            }
+            OptionKind::Bool { .. } => match text {
+                "true" => Ok(OptionValue::Bool(true)),
+                "false" => Ok(OptionValue::Bool(false)),
+                _ => Err(invalid()),
+            },
+            OptionKind::Choice { choices, .. } => choices
+                .iter()
+                .find(|choice| **choice == text)
+                .map(|choice| OptionValue::Choice(choice))
+                .ok_or_else(out_of_range),
+        }
+    }
+}
+
+impl std::fmt::Display for EngineOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            OptionKind::Int {
+                value,
+                default,
+                min,
+                max,
+            } => write!(
+                f,
+                "{} int value {} default {} min {} max {}",
+                self.name, value, default, min, max
+            ),
+            // engines keep their settings in f32, which prints without the digits of rounding
+            OptionKind::Float {
+                value,
+                default,
+                min,
+                max,
+            } => write!(
+                f,
+                "{} float value {} default {} min {} max {}",
+                self.name, value as f32, default as f32, min, max
+            ),
+            OptionKind::Bool { value, default } => {
+                write!(f, "{} bool value {} default {}", self.name, value, default)
+            }
+            OptionKind::Choice {
+                value,
+                default,
+                choices,
+            } => write!(
+                f,
+                "{} choice value {} default {} choices {}",
+                self.name,
+                value,
+                default,
+                choices.join(" ")
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for OptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OptionError::UnknownOption(name) => write!(f, "unknown option `{}`", name),
+            OptionError::InvalidValue(text) => write!(f, "invalid option value `{}`", text),
+            OptionError::OutOfRange(text) => write!(f, "option value `{}` out of range", text),
+        }
+    }
+}
+
+/// Set an engine option from text of the form `<engine>.<option> <value>`
+fn set_engine_option<G: Geometry>(
+    engine: &mut dyn Engine<G>,
+    text: &str,
+) -> Result<(), OptionError> {
+    let (path, value) = text
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| OptionError::InvalidValue(text.trim().to_string()))?;
+    match path.split_once('.') {
+        Some((engine_name, name)) if engine_name == engine.get_name() => {
+            set_named_option(engine, name, value)
+        }
+        _ => Err(OptionError::UnknownOption(path.to_string())),
+    }
+}
+
+/// Set an engine option by its name, from the text of its value
+fn set_named_option<G: Geometry>(
+    engine: &mut dyn Engine<G>,
+    name: &str,
+    value: &str,
+) -> Result<(), OptionError> {
+    let option = engine
+        .get_options()
+        .into_iter()
+        .find(|option| option.name == name)
+        .ok_or_else(|| OptionError::UnknownOption(name.to_string()))?;
+    let value = option.parse(value.trim())?;
+    engine.set_option(name, value)
+}
+
+/// Lets the caller of a search stop it early and watch its progress
+struct SearchControl<'a> {
+    stop: &'a AtomicBool,
+    info: Option<&'a mut (dyn FnMut(&SearchInfo) + Send)>,
+    /// The time control, and the time left on the clock for the move
+    time: Option<(TimeControl, Duration)>,
+}
+
+impl<'a> SearchControl<'a> {
+    /// The search stops as soon as possible once `stop` is set, possibly from another thread
+    fn new(stop: &'a AtomicBool) -> Self {
+        Self {
+            stop,
+            info: None,
+            time: None,
+        }
+    }
+
+    /// Have `info` called with a progress report from time to time during the search
+    fn with_info(self, info: &'a mut (dyn FnMut(&SearchInfo) + Send)) -> Self {
+        Self {
+            info: Some(info),
+            ..self
+        }
+    }
+
+    /// Let the search pick its time budget from the time left on the clock, if there is one
+    fn with_time(self, time: Option<(TimeControl, Duration)>) -> Self {
+        Self { time, ..self }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    fn report(&mut self, info: &SearchInfo) {
+        if let Some(callback) = &mut self.info {
+            callback(info);
+        }
+    }
+}
+
+/// Progress report of a running search
+#[derive(Debug)]
+struct SearchInfo {
+    iterations: u32,
+    nodes: usize,
+    best_move: Move,
+    /// Win rate of the best move, where a draw counts as half a win
+    win_rate: f32,
+}
+
+/// Statistics of a finished search, to tune the search settings
+#[derive(Debug)]
+struct SearchStats {
+    /// The moves that lead to the searched position
+    position: Vec<Move>,
+    played: Action,
+    iterations: u32,
+    /// Number of nodes in the tree, including the ones kept from earlier searches
+    nodes: usize,
+    /// Number of moves along the longest path of the tree
+    depth: usize,
+    elapsed: Duration,
+    playouts: u64,
+    /// The moves of the root, with the number of times the search visited each one and its
+    /// win rate
+    children: Vec<(Move, u32, f32)>,
+}
+
+/// Print the statistics of a search of an engine as a single line of JSON
+fn print_stats<G: Geometry>(engine: &str, stats: &SearchStats) -> String {
+    let position: Vec<String> = stats.position.iter().map(print_move::<G>).collect();
+    let children: Vec<String> = stats
+        .children
+        .iter()
+        .map(|(mv, visits, value)| {
+            // a move that wasn't visited has no win rate
+            let value = if *visits > 0 {
+                format!("{:.4}", value)
+            } else {
+                "null".to_string()
+            };
+            format!(
+                "{{\"move\":\"{}\",\"visits\":{},\"value\":{}}}",
+                print_move::<G>(mv),
+                visits,
+                value
+            )
+        })
+        .collect();
+    let seconds = stats.elapsed.as_secs_f64();
+    let playouts_per_second = if seconds > 0.0 {
+        stats.playouts as f64 / seconds
+    } else {
+        0.0
+    };
+    format!(
+        "{{\"engine\":\"{}\",\"board\":\"{}\",\"position\":\"{}\",\"move\":\"{}\",\
+         \"iterations\":{},\"nodes\":{},\"depth\":{},\"time\":{:.6},\
+         \"playouts_per_second\":{:.0},\"children\":[{}]}}",
+        engine,
+        G::BOARD,
+        position.join(" "),
+        match &stats.played {
+            Action::Move(mv) => print_move::<G>(mv),
+            Action::Resign => "resign".to_string(),
+        },
+        stats.iterations,
+        stats.nodes,
+        stats.depth,
+        seconds,
+        playouts_per_second,
+        children.join(",")
+    )
+}
+
+/// Append a line of text to a file, creating the file if needed
+fn append_line(path: &str, line: &str) -> Result<(), io::Error> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", line)
+}
+
+fn print_info<G: Geometry>(info: &SearchInfo) -> String {
+    format!(
+        "iterations {} nodes {} best {} winrate {:.3}",
+        info.iterations,
+        info.nodes,
+        print_move::<G>(&info.best_move),
+        info.win_rate,
+    )
+}
+
+struct Rando {
+    pcg: Pcg,
+}
+
+impl Rando {
+    fn new() -> Self {
+        Self { pcg: Pcg::new() }
+    }
+}
+
+impl<G: Geometry> Engine<G> for Rando {
+    fn get_name(&self) -> &'static str {
+        "rando"
+    }
+
+    fn play(&mut self, game: &Game<G>, _control: &mut SearchControl) -> Action {
+        let mut moves: Vec<Move> = game.legal_moves().collect();
+        if moves.is_empty() {
+            // a game that's over has no moves left
+            return Action::Resign;
+        }
+        let index = self.pcg.next_u64() % moves.len() as u64;
+        let response = moves.swap_remove(index as usize);
+        if game.rules().calling {
+            // claim a quarto whenever possible
+            let claim = Move {
+                spot: response.spot,
+                piece: None,
+            };
+            let mut temp = game.clone();
+            if temp.apply(&claim).is_ok() && temp.history().is_decided(temp.turn()) {
+                return Action::Move(claim);
+            }
+        }
+        Action::Move(response)
+    }
+}
+
+/// Engine that looks one move ahead
+///
+/// It completes a quarto whenever it can, and never gives a piece that lets the opponent
+/// complete one, unless every piece does. In misère, it avoids completing a quarto, and gives
+/// a piece that forces the opponent to complete one if there is such a piece.
+/// Remaining ties are broken at random.
+struct Greedy {
+    pcg: Pcg,
+}
+
+impl Greedy {
+    fn new() -> Self {
+        Self { pcg: Pcg::new() }
+    }
+}
+
+impl<G: Geometry> Engine<G> for Greedy {
+    fn get_name(&self) -> &'static str {
+        "greedy"
+    }
+
+    fn play(&mut self, game: &Game<G>, _control: &mut SearchControl) -> Action {
+        if game.outcome() != GameResult::InProgress {
+            // a game that's over has no moves left
+            return Action::Resign;
+        }
+        let misere = game.rules().misere;
+        let position = game.position();
+        let moves: Vec<Move> = game.legal_moves().collect();
+
+        // score every move, where a higher score is better
+        let mut best_score = 0;
+        let mut best_moves = vec![];
+        for &mv in &moves {
+            let end = Move {
+                spot: mv.spot,
+                piece: None,
+            };
+            let score = match mv.piece {
+                None => {
+                    let mut temp = game.clone();
+                    temp.apply_legal(&mv);
+                    if temp.history().is_decided(temp.turn()) {
+                        // completing or claiming a quarto ends the game
+                        if misere {
+                            0
+                        } else {
+                            3
+                        }
+                    } else {
+                        // placing the last piece without a quarto is a draw
+                        1
+                    }
+                }
+                // when placing the piece ends the game, giving one doesn't make sense
+                Some(_) if moves.contains(&end) => continue,
+                Some(piece) => {
+                    let next = position.after_move(&mv);
+                    let opponent_spots = next.free_spots();
+                    // count the spots where the opponent would complete a quarto with this piece
+                    let quarto_count = opponent_spots
+                        .iter()
+                        .filter(|s| {
+                            let mut after = next.clone();
+                            after.place_piece(**s, piece);
+                            after.is_quarto_at(**s)
+                        })
+                        .count();
+                    if misere {
+                        if quarto_count == opponent_spots.len() {
+                            3
+                        } else {
+                            2
+                        }
+                    } else if quarto_count == 0 {
+                        2
+                    } else {
+                        1
+                    }
+                }
+            };
+            if score > best_score || best_moves.is_empty() {
+                best_score = score;
+                best_moves.clear();
+            }
+            if score == best_score {
+                best_moves.push(mv);
+            }
+        }
+        let index = self.pcg.next_u64() % best_moves.len() as u64;
+        Action::Move(best_moves.swap_remove(index as usize))
+    }
+}
+
+/// The ways Bruto can pick the move to play once the search is over
+///
+/// - `max` - the highest win rate
+/// - `robust` - the most visits
+/// - `secure` - the highest lower confidence bound of the win rate
+/// - `max_robust` - the highest win rate and the most visits, searching on until a move has both
+const FINAL_SELECTION_NAMES: &[&str] = &["max", "robust", "secure", "max_robust"];
+
+struct Bruto<G: Geometry> {
+    pcg: Pcg,
+    /// The search tree, with the root at index 0
+    nodes: Vec<Node<G>>,
+    /// The turn of the root node, kept to reuse the tree on later turns
+    root_turn: i8,
+    /// Number of tree expansions per move
+    iterations: u32,
+    temperature_factor: f32,
+    playout_batch_size: u32,
+    /// Pondering, and searching with a clock, stop growing the tree beyond this many nodes
+    ponder_node_limit: usize,
+    /// Upper bound of the random noise added to win rates when picking the move to play
+    move_noise: f32,
+    /// Chance of playing a uniformly random move instead of the best one
+    blunder_probability: f32,
+    /// Resign when the win rate of the best move is below this, where 0 never resigns
+    resign_threshold: f32,
+    /// Resign when the position is proven lost by the tablebase or the endgame solver
+    resign_proven_loss: bool,
+    /// Accept a draw offer when the win rate is below this, or the position is proven not won
+    draw_threshold: f32,
+    tablebase: Option<Arc<Tablebase<G>>>,
+    book: Option<Arc<Book<G>>>,
+    /// Whether to play from the book when there is one
+    use_book: bool,
+    evaluator: Option<Arc<Evaluator<G>>>,
+    /// Whether to search with the evaluator when there is one, which then gives the priors of
+    /// the moves and the values of new nodes
+    use_evaluator: bool,
+    /// How much the value of the evaluator counts at a new node, against random playouts
+    value_weight: f32,
+    /// How much PUCT explores the moves with high priors that were visited little
+    puct_factor: f32,
+    /// The name of the selection policy, one of `SELECTION_NAMES`
+    selection: &'static str,
+    /// How to pick the move to play from the searched moves, one of `FINAL_SELECTION_NAMES`
+    final_selection: &'static str,
+    /// Number of random playouts so far, to measure their speed
+    playouts: u64,
+    stats: Option<SearchStats>,
+}
+
+#[derive(Debug, Clone)]
+struct Node<G: Geometry> {
+    value: u32,
+    count: u32,
+    child_count: usize,
+    first_child: usize,
+    /// Sum of the squared win rates of the visits, for the variance of the results
+    square_sum: f32,
+    /// Prior of the move into this node among its siblings, for PUCT
+    prior: f32,
+    history: History<G>,
+}
+
+impl<G: Geometry> Engine<G> for Bruto<G> {
+    fn get_name(&self) -> &'static str {
+        "bruto"
+    }
+
+    fn play(&mut self, game: &Game<G>, control: &mut SearchControl) -> Action {
+        self.stats = None;
+        let proven_value = self.proven_value(game);
+        if self.resign_proven_loss && proven_value == Some(-1) {
+            return Action::Resign;
+        }
+        let tablebase_move = self
+            .tablebase_for(game.rules())
+            .and_then(|tablebase| tablebase.best_move(game));
+        if let Some(mv) = tablebase_move {
+            return Action::Move(mv);
+        }
+        if self.use_book {
+            let book = self
+                .book
+                .as_deref()
+                .filter(|book| book.rules == game.rules());
+            if let Some(mv) = book.and_then(|book| book.pick_move(game, &mut self.pcg)) {
+                return Action::Move(mv);
+            }
+        }
+        let turn = game.turn();
+        if game.outcome() == GameResult::InProgress {
+            self.set_root(game);
+            // with a clock, search until the time budget is spent instead of for a fixed number
+            // of iterations, unless the position is proven and the search can't change its value
+            let budget = control
+                .time
+                .filter(|_| proven_value.is_none())
+                .map(|(time_control, time_left)| self.search_time(time_control, time_left, turn));
+            let start = Instant::now();
+            let start_playouts = self.playouts;
+            let mut i = 0;
+            loop {
+                // max-robust selection searches on, for up to half as long again, until the
+                // move with the most visits also has the highest win rate
+                let (done, overtime) = match budget {
+                    Some((budget, limit)) => {
+                        let full = self.nodes.len() >= self.ponder_node_limit;
+                        (
+                            start.elapsed() >= budget || full,
+                            start.elapsed() >= limit || full,
+                        )
+                    }
+                    None => (
+                        i >= self.iterations,
+                        i >= self.iterations + self.iterations / 2,
+                    ),
+                };
+                let done = done
+                    && (overtime
+                        || self.final_selection != "max_robust"
+                        || self.nodes[0].child_count == 0
+                        || self.best_child() == self.most_visited_child());
+                // a stopped search still needs the root's children to pick from
+                if (done || control.is_stopped() || !self.has_room())
+                    && self.nodes[0].child_count > 0
+                {
+                    break;
+                }
+                self.expand(0, turn);
+                i += 1;
+                if i % 100 == 0 {
+                    control.report(&self.get_info(i, turn));
+                }
+            }
+            if i % 100 != 0 {
+                control.report(&self.get_info(i, turn));
+            }
+            let mut stats = SearchStats {
+                position: (0..turn).map(|t| game.history().get_move(t)).collect(),
+                played: Action::Resign,
+                iterations: i,
+                nodes: self.nodes.len(),
+                depth: self.tree_depth(),
+                elapsed: start.elapsed(),
+                playouts: self.playouts - start_playouts,
+                children: self.root_children(turn),
+            };
+            let best = &self.nodes[self.best_child()];
+            if (best.value as f32 / best.count as f32) < self.resign_threshold {
+                self.stats = Some(stats);
+                return Action::Resign;
+            }
+            let best_index = self.pick_child();
+            let action = Action::Move(self.nodes[best_index].history.get_move(turn));
+            stats.played = action;
+            self.stats = Some(stats);
+            action
+        } else {
+            // a game that's over has no moves left
+            Action::Resign
+        }
+    }
+
+    fn ponder(&mut self, game: &Game<G>, control: &mut SearchControl) {
+        let turn = game.turn();
+        if game.outcome() == GameResult::InProgress {
+            // the search can't change the value of a proven position
+            if self.proven_value(game).is_some() {
+                return;
+            }
+            self.set_root(game);
+            let mut iterations = 0;
+            while !control.is_stopped()
+                && self.nodes.len() < self.ponder_node_limit
+                && self.has_room()
+            {
+                self.expand(0, turn);
+                iterations += 1;
+                if iterations % 100 == 0 {
+                    control.report(&self.get_info(iterations, turn));
+                }
+            }
+        }
+    }
+
+    fn get_options(&self) -> Vec<EngineOption> {
+        let default = Self::new();
+        vec![
+            EngineOption {
+                name: "iterations",
+                kind: OptionKind::Int {
+                    value: self.iterations as i64,
+                    default: default.iterations as i64,
+                    min: 1,
+                    max: 1_000_000,
+                },
+            },
+            EngineOption {
+                name: "temperature_factor",
+                kind: OptionKind::Float {
+                    value: self.temperature_factor as f64,
+                    default: default.temperature_factor as f64,
+                    min: 0.0,
+                    max: 10.0,
+                },
+            },
+            EngineOption {
+                name: "playout_batch_size",
+                kind: OptionKind::Int {
+                    value: self.playout_batch_size as i64,
+                    default: default.playout_batch_size as i64,
+                    min: 1,
+                    max: 1_000_000,
+                },
+            },
+            EngineOption {
+                name: "ponder_node_limit",
+                kind: OptionKind::Int {
+                    value: self.ponder_node_limit as i64,
+                    default: default.ponder_node_limit as i64,
+                    min: 1,
+                    max: 100_000_000,
+                },
+            },
+            EngineOption {
+                name: "move_noise",
+                kind: OptionKind::Float {
+                    value: self.move_noise as f64,
+                    default: default.move_noise as f64,
+                    min: 0.0,
+                    max: 1.0,
+                },
+            },
+            EngineOption {
+                name: "blunder_probability",
+                kind: OptionKind::Float {
+                    value: self.blunder_probability as f64,
+                    default: default.blunder_probability as f64,
+                    min: 0.0,
+                    max: 1.0,
+                },
+            },
+            EngineOption {
+                name: "resign_threshold",
+                kind: OptionKind::Float {
+                    value: self.resign_threshold as f64,
+                    default: default.resign_threshold as f64,
+                    min: 0.0,
+                    max: 1.0,
+                },
+            },
+            EngineOption {
+                name: "resign_proven_loss",
+                kind: OptionKind::Bool {
+                    value: self.resign_proven_loss,
+                    default: default.resign_proven_loss,
+                },
+            },
+            EngineOption {
+                name: "draw_threshold",
+                kind: OptionKind::Float {
+                    value: self.draw_threshold as f64,
+                    default: default.draw_threshold as f64,
+                    min: 0.0,
+                    max: 1.0,
+                },
+            },
+            EngineOption {
+                name: "use_book",
+                kind: OptionKind::Bool {
+                    value: self.use_book,
+                    default: default.use_book,
+                },
+            },
+            EngineOption {
+                name: "use_evaluator",
+                kind: OptionKind::Bool {
+                    value: self.use_evaluator,
+                    default: default.use_evaluator,
+                },
+            },
+            EngineOption {
+                name: "value_weight",
+                kind: OptionKind::Float {
+                    value: self.value_weight as f64,
+                    default: default.value_weight as f64,
+                    min: 0.0,
+                    max: 1.0,
+                },
+            },
+            EngineOption {
+                name: "puct_factor",
+                kind: OptionKind::Float {
+                    value: self.puct_factor as f64,
+                    default: default.puct_factor as f64,
+                    min: 0.0,
+                    max: 10.0,
+                },
+            },
+            EngineOption {
+                name: "selection",
+                kind: OptionKind::Choice {
+                    value: self.selection,
+                    default: default.selection,
+                    choices: SELECTION_NAMES,
+                },
+            },
+            EngineOption {
+                name: "final_selection",
+                kind: OptionKind::Choice {
+                    value: self.final_selection,
+                    default: default.final_selection,
+                    choices: FINAL_SELECTION_NAMES,
+                },
+            },
+        ]
+    }
+
+    fn set_option(&mut self, name: &str, value: OptionValue) -> Result<(), OptionError> {
+        match (name, value) {
+            ("iterations", OptionValue::Int(value)) => self.iterations = value as u32,
+            ("temperature_factor", OptionValue::Float(value)) => {
+                self.temperature_factor = value as f32;
+            }
+            ("playout_batch_size", OptionValue::Int(value)) => {
+                self.playout_batch_size = value as u32;
+            }
+            ("ponder_node_limit", OptionValue::Int(value)) => {
+                self.ponder_node_limit = value as usize;
+            }
+            ("move_noise", OptionValue::Float(value)) => self.move_noise = value as f32,
+            ("blunder_probability", OptionValue::Float(value)) => {
+                self.blunder_probability = value as f32;
+            }
+            ("resign_threshold", OptionValue::Float(value)) => {
+                self.resign_threshold = value as f32;
+            }
+            ("resign_proven_loss", OptionValue::Bool(value)) => self.resign_proven_loss = value,
+            ("draw_threshold", OptionValue::Float(value)) => self.draw_threshold = value as f32,
+            ("use_book", OptionValue::Bool(value)) => self.use_book = value,
+            ("use_evaluator", OptionValue::Bool(value)) => self.use_evaluator = value,
+            ("value_weight", OptionValue::Float(value)) => self.value_weight = value as f32,
+            ("puct_factor", OptionValue::Float(value)) => self.puct_factor = value as f32,
+            ("selection", OptionValue::Choice(value)) => self.selection = value,
+            ("final_selection", OptionValue::Choice(value)) => self.final_selection = value,
+            _ => return Err(OptionError::UnknownOption(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn set_tablebase(&mut self, tablebase: Arc<Tablebase<G>>) {
+        self.tablebase = Some(tablebase);
+    }
+
+    fn set_book(&mut self, book: Arc<Book<G>>) {
+        self.book = Some(book);
+    }
+
+    fn set_evaluator(&mut self, evaluator: Arc<Evaluator<G>>) {
+        self.evaluator = Some(evaluator);
+    }
+
+    fn search_stats(&self) -> Option<&SearchStats> {
+        self.stats.as_ref()
+    }
+
+    fn print_tree(&self, filter: &TreeFilter) -> Option<String> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let label = |n: usize, mv: &str| {
+            let node = &self.nodes[n];
+            format!(
+                "n{} [label=\"{}\\n{}/{} {:.3}\"];",
+                n,
+                mv,
+                node.value,
+                node.count,
+                node.value as f32 / node.count as f32
+            )
+        };
+        let mut lines = vec![
+            "digraph tree {".to_string(),
+            "    node [shape=box, fontname=\"monospace\"];".to_string(),
+            format!("    {}", label(0, "root")),
+        ];
+        // depth-first from the root, where a node at depth `d` was reached by the move made on
+        // turn `root_turn + d - 1`
+        let mut stack = vec![(0, 0)];
+        while let Some((n, depth)) = stack.pop() {
+            if depth >= filter.max_depth {
+                continue;
+            }
+            let node = &self.nodes[n];
+            let mut children: Vec<usize> = (node.first_child
+                ..(node.first_child + node.child_count))
+                .filter(|k| {
+                    self.nodes[*k].count / (2 * self.playout_batch_size) >= filter.min_visits
+                })
+                .collect();
+            children.sort_by(|a, b| self.nodes[*b].count.cmp(&self.nodes[*a].count));
+            children.truncate(filter.top.unwrap_or(children.len()));
+            let turn = self.root_turn + depth as i8;
+            for k in &children {
+                let mv = print_move::<G>(&self.nodes[*k].history.get_move(turn));
+                lines.push(format!("    {}", label(*k, &mv)));
+                lines.push(format!("    n{} -> n{};", n, k));
+            }
+            // the most visited child is exported first
+            stack.extend(children.into_iter().rev().map(|k| (k, depth + 1)));
+        }
+        lines.push("}".to_string());
+        Some(lines.join("\n") + "\n")
+    }
+
+    fn accept_draw(&mut self, game: &Game<G>) -> bool {
+        if let Some(value) = self.proven_value(game) {
+            // the value is for the opponent, who is to move
+            return value >= 0;
+        }
+        self.set_root(game);
+        for _ in 0..self.iterations {
+            self.expand(0, game.turn());
+        }
+        // the root's value is for the player who moved into it
+        let root = &self.nodes[0];
+        (root.value as f32 / root.count as f32) < self.draw_threshold
+    }
+}
+
+impl<G: Geometry> Bruto<G> {
+    fn new() -> Self {
+        Self {
+            pcg: Pcg::new(),
+            nodes: vec![],
+            root_turn: 0,
+            iterations: 1000,
+            temperature_factor: 0.35,
+            playout_batch_size: 1000,
+            ponder_node_limit: 2_000_000,
+            move_noise: 0.0,
+            blunder_probability: 0.0,
+            resign_threshold: 0.0,
+            resign_proven_loss: true,
+            draw_threshold: 0.5,
+            tablebase: None,
+            book: None,
+            use_book: true,
+            evaluator: None,
+            use_evaluator: true,
+            value_weight: 1.0,
+            puct_factor: 1.5,
+            selection: "auto",
+            final_selection: "max",
+            playouts: 0,
+            stats: None,
+        }
+    }
+
+    /// Create an engine of the given difficulty level from 1 (weakest) to 10 (full strength)
+    ///
+    /// Lower levels search less, add more noise to the win rates of the moves, and blunder more.
+    fn with_level(level: u8) -> Option<Self> {
+        const LEVEL_ITERATIONS: [u32; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+        if !(1..=10).contains(&level) {
+            return None;
+        }
+        let weakness = (10 - level) as f32;
+        Some(Self {
+            iterations: LEVEL_ITERATIONS[level as usize - 1],
+            move_noise: 0.05 * weakness,
+            blunder_probability: 0.03 * weakness,
+            ..Self::new()
+        })
+    }
+
+    /// Whether the counts of the root, which bound those of all other nodes, can take another
+    /// iteration without overflowing
+    ///
+    /// The tree stops growing once its leaves are all at the end of the game, so a long search
+    /// would otherwise run the counts past their limit.
+    fn has_room(&self) -> bool {
+        self.nodes[0].count <= u32::MAX - 2 * self.playout_batch_size
+    }
+
+    /// The tablebase, if there is one for `rules`
+    fn tablebase_for(&self, rules: Rules) -> Option<&Tablebase<G>> {
+        self.tablebase
+            .as_deref()
+            .filter(|tablebase| tablebase.rules == rules)
+    }
+
+    /// The evaluator, if there is one for `rules` and it's used
+    fn evaluator_for(&self, rules: Rules) -> Option<&Evaluator<G>> {
+        self.evaluator
+            .as_deref()
+            .filter(|evaluator| self.use_evaluator && evaluator.rules == rules)
+    }
+
+    /// The selection policy to search positions under `rules` with
+    fn selection_policy(&self, rules: Rules) -> Selection {
+        match self.selection {
+            "ucb1" => Selection::Ucb1(Ucb1 {
+                temperature: self.temperature_factor,
+            }),
+            "ucb1_tuned" => Selection::Ucb1Tuned(Ucb1Tuned),
+            "puct" => Selection::Puct(Puct {
+                factor: self.puct_factor,
+            }),
+            "thompson" => Selection::Thompson(Thompson),
+            _ if self.evaluator_for(rules).is_some() => Selection::Puct(Puct {
+                factor: self.puct_factor,
+            }),
+            _ => Selection::Ucb1(Ucb1 {
+                temperature: self.temperature_factor,
+            }),
+        }
+    }
+
+    /// What the search knows about node `n`
+    fn node_stats(&self, n: usize) -> NodeStats {
+        let node = &self.nodes[n];
+        let visits = (node.count / (2 * self.playout_batch_size)) as f32;
+        NodeStats {
+            win_rate: if node.count > 0 {
+                node.value as f32 / node.count as f32
+            } else {
+                0.0
+            },
+            square_rate: if visits > 0.0 {
+                node.square_sum / visits
+            } else {
+                0.0
+            },
+            visits,
+            count: node.count as f32,
+            prior: node.prior,
+        }
+    }
+
+    /// The exact value of the position for the player to move, if it's in the tablebase or
+    /// close enough to the end of the game to be solved right away
+    fn proven_value(&self, game: &Game<G>) -> Option<i8> {
+        let (position, turn) = (game.position(), game.turn());
+        let outcome = self
+            .tablebase_for(game.rules())
+            .and_then(|tablebase| tablebase.probe(position, turn));
+        match outcome {
+            Some(outcome) => Some(outcome.value),
+            None => solver::solve_endgame(position, turn, game.rules()).map(|(value, _)| value),
+        }
+    }
+
+    /// How long to search on `turn`, given the time left on the clock
+    ///
+    /// The time left is spread over the remaining moves, with more of it for the moves in the
+    /// middle of the game, which are the hardest to get right: early on, most moves are about
+    /// equally good, and late in the game the tree is small enough to search quickly.
+    fn time_budget(&self, time_control: TimeControl, time_left: Duration, turn: i8) -> Duration {
+        let usable = usable_time(time_left);
+        match time_control {
+            TimeControl::PerMove(_) => usable,
+            TimeControl::Increment { increment, .. } => {
+                // from 1 at both ends of the game up to 3 in the middle
+                let weight = |t: i8| 3.0 - 2.0 * (2.0 * t as f32 / G::LAST_TURN as f32 - 1.0).abs();
+                let total: f32 = (turn..=G::LAST_TURN).step_by(2).map(weight).sum();
+                (usable.mul_f32(weight(turn) / total) + increment).min(usable)
+            }
+        }
+    }
+
+    /// The time budget of a search on `turn`, and the time that max-robust selection can search
+    /// on for, which is half the budget again but never more than the clock allows
+    fn search_time(
+        &self,
+        time_control: TimeControl,
+        time_left: Duration,
+        turn: i8,
+    ) -> (Duration, Duration) {
+        let budget = self.time_budget(time_control, time_left, turn);
+        (budget, budget.mul_f32(1.5).min(usable_time(time_left)))
+    }
+
+    /// Pick the child of the root with the best win rate
+    fn best_child(&self) -> usize {
+        self.root_child_by(|child| child.value as f32 / child.count as f32)
+    }
 
-trait Engine {
-    fn get_name(&self) -> &'static str;
-    fn play(&mut self, history: &History, turn: i8) -> Move;
-}
+    fn most_visited_child(&self) -> usize {
+        self.root_child_by(|child| child.count as f32)
+    }
 
-struct Rando {
-    pcg: Pcg,
-}
+    /// The child of the root with the highest lower confidence bound of its win rate, which is
+    /// the mirror image of the upper bound of UCB1
+    fn secure_child(&self) -> usize {
+        let ln_n = f32::ln(self.nodes[0].count as f32);
+        self.root_child_by(|child| {
+            child.value as f32 / child.count as f32
+                - self.temperature_factor * f32::sqrt(ln_n / child.count as f32)
+        })
+    }
 
-impl Rando {
-    fn new() -> Self {
-        Self { pcg: Pcg::new() }
+    /// The child of the root with the highest score, among the ones that were visited
+    fn root_child_by(&self, score: impl Fn(&Node<G>) -> f32) -> usize {
+        let node = &self.nodes[0];
+        let mut best_value = f32::NEG_INFINITY;
+        let mut best_index = node.first_child;
+        for k in node.first_child..(node.first_child + node.child_count) {
+            let child = &self.nodes[k];
+            if child.count == 0 {
+                continue;
+            }
+            let value = score(child);
+            if value > best_value {
+                best_value = value;
+                best_index = k;
+            }
+        }
+        best_index
     }
-}
 
-impl Engine for Rando {
-    fn get_name(&self) -> &'static str {
-        "rando"
+    /// The child of the root to play at full strength
+    ///
+    /// A move that the endgame solver proves best comes first, and otherwise the final selection
+    /// strategy picks the move. Max-robust selection falls back to the most visits when the
+    /// search ran out of time before a move had both.
+    fn final_child(&self) -> usize {
+        let (root, turn) = (&self.nodes[0], self.root_turn);
+        let proven =
+            solver::solve_endgame(&root.history.get_position(turn), turn, root.history.rules)
+                .and_then(|(_, mv)| {
+                    (root.first_child..(root.first_child + root.child_count))
+                        .find(|k| self.nodes[*k].history.get_move(turn) == mv)
+                });
+        if let Some(k) = proven {
+            return k;
+        }
+        match self.final_selection {
+            "robust" | "max_robust" => self.most_visited_child(),
+            "secure" => self.secure_child(),
+            _ => self.best_child(),
+        }
     }
 
-    fn play(&mut self, history: &History, turn: i8) -> Move {
-        let mut response = Move {
-            spot: None,
-            piece: None,
-        };
-        if turn >= 1 && turn <= 16 {
-            // pick a spot
-            let spot_random_source = self.pcg.rand_16_fact();
-            let free_spot_count = 17 - turn as u64;
-            let spot_index = (spot_random_source % free_spot_count) as i8;
-            response.spot = Some(history.get_raw_spot(turn - 1 + spot_index));
+    /// Pick the child of the root to play, weakened by the move noise and blunder settings
+    fn pick_child(&mut self) -> usize {
+        let node = &self.nodes[0];
+        let (first_child, child_count) = (node.first_child, node.child_count);
+        if self.blunder_probability > 0.0 && self.pcg.rand_f32() < self.blunder_probability {
+            return first_child + (self.pcg.next_u64() % child_count as u64) as usize;
         }
-        if turn >= 0 && turn <= 15 {
-            // pick a piece
-            let piece_random_source = self.pcg.rand_16_fact();
-            let free_piece_count = 16 - turn as u64;
-            let piece_index = (piece_random_source % free_piece_count) as i8;
-            response.piece = Some(history.get_raw_piece(turn + piece_index));
+        if self.move_noise == 0.0 {
+            return self.final_child();
+        }
+        let mut best_value = 0.0;
+        let mut best_index = first_child;
+        for k in first_child..(first_child + child_count) {
+            let child = &self.nodes[k];
+            let value =
+                child.value as f32 / child.count as f32 + self.move_noise * self.pcg.rand_f32();
+            if value > best_value {
+                best_value = value;
+                best_index = k;
+            }
         }
-        response
+        best_index
     }
-}
 
-struct Bruto {
-    pcg: Pcg,
-    nodes: Vec<Node>,
-    temperature_factor: f32,
-    playout_batch_size: u32,
-}
+    /// The moves of the root, with the number of times the search visited each one and its win
+    /// rate
+    fn root_children(&self, turn: i8) -> Vec<(Move, u32, f32)> {
+        let node = &self.nodes[0];
+        self.nodes[node.first_child..(node.first_child + node.child_count)]
+            .iter()
+            .map(|child| {
+                let visits = child.count / (2 * self.playout_batch_size);
+                let value = child.value as f32 / child.count as f32;
+                (child.history.get_move(turn), visits, value)
+            })
+            .collect()
+    }
 
-#[derive(Debug, Clone)]
-struct Node {
-    value: u32,
-    count: u32,
-    child_count: usize,
-    first_child: usize,
-    history: History,
-}
+    /// Number of moves along the longest path from the root
+    fn tree_depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = vec![(0, 0)];
+        while let Some((n, d)) = stack.pop() {
+            depth = depth.max(d);
+            let node = &self.nodes[n];
+            stack.extend(
+                (node.first_child..(node.first_child + node.child_count)).map(|k| (k, d + 1)),
+            );
+        }
+        depth
+    }
 
-impl Engine for Bruto {
-    fn get_name(&self) -> &'static str {
-        "bruto"
+    fn get_info(&self, iterations: u32, turn: i8) -> SearchInfo {
+        let best = &self.nodes[self.best_child()];
+        SearchInfo {
+            iterations,
+            nodes: self.nodes.len(),
+            best_move: best.history.get_move(turn),
+            win_rate: best.value as f32 / best.count as f32,
+        }
     }
 
-    fn play(&mut self, history: &History, turn: i8) -> Move {
-        if turn >= 0 && turn <= 16 {
-            self.nodes.clear();
-            self.nodes.push(Node {
-                value: 0,
-                count: 0,
-                child_count: 0,
-                first_child: 0,
-                history: history.clone(),
-            });
-            for _i in 0..1000 {
-                self.expand(0, turn);
-            }
-            // pick best move
-            let node = &self.nodes[0];
-            let mut best_value = 0.0;
-            let mut best_index = node.first_child;
-            for k in node.first_child..(node.first_child + node.child_count) {
-                let child = &self.nodes[k];
-                let value = child.value as f32 / child.count as f32;
-                if value > best_value {
-                    best_value = value;
-                    best_index = k;
-                }
-            }
-            Move {
-                spot: self.nodes[best_index].history.get_spot(turn),
-                piece: self.nodes[best_index].history.get_piece(turn),
-            }
-        } else {
-            Move {
-                spot: None,
-                piece: None,
+    /// Make the node for the current position of `game` the root of the tree
+    ///
+    /// The subtree below the matching node is kept if the position was already searched,
+    /// e.g. while pondering during the opponent's turn. Otherwise the tree starts from scratch.
+    fn set_root(&mut self, game: &Game<G>) {
+        let (history, turn) = (game.history(), game.turn());
+        match self.find_node(history, turn) {
+            Some(n) => self.reroot(n),
+            None => {
+                self.nodes.clear();
+                self.nodes.push(Node {
+                    value: 0,
+                    count: 0,
+                    child_count: 0,
+                    first_child: 0,
+                    square_sum: 0.0,
+                    prior: 1.0,
+                    history: history.clone(),
+                });
             }
         }
+        self.root_turn = turn;
     }
-}
 
-impl Bruto {
-    fn new() -> Self {
-        Self {
-            pcg: Pcg::new(),
-            nodes: vec![],
-            temperature_factor: 0.5,
-            playout_batch_size: 1000,
+    /// Find the node for the position at `turn` by following the moves of `history` from the root
+    fn find_node(&self, history: &History<G>, turn: i8) -> Option<usize> {
+        let root = self.nodes.first()?;
+        if self.root_turn > turn {
+            return None;
+        }
+        // the root must have been reached by the same moves, under the same rules
+        if root.history.rules != history.rules {
+            return None;
+        }
+        if (0..self.root_turn).any(|t| root.history.get_move(t) != history.get_move(t)) {
+            return None;
+        }
+        let mut n = 0;
+        for t in self.root_turn..turn {
+            let node = &self.nodes[n];
+            n = (node.first_child..(node.first_child + node.child_count))
+                .find(|k| self.nodes[*k].history.get_move(t) == history.get_move(t))?;
+        }
+        Some(n)
+    }
+
+    /// Discard everything outside the subtree of node `n`, which becomes the new root
+    fn reroot(&mut self, n: usize) {
+        if n == 0 {
+            return;
         }
+        // copy breadth-first, so that siblings stay next to each other
+        let mut nodes = vec![self.nodes[n].clone()];
+        let mut i = 0;
+        while i < nodes.len() {
+            let first_child = nodes[i].first_child;
+            let child_count = nodes[i].child_count;
+            nodes[i].first_child = nodes.len();
+            nodes.extend_from_slice(&self.nodes[first_child..(first_child + child_count)]);
+            i += 1;
+        }
+        self.nodes = nodes;
     }
 
+    /// Grow the subtree of node `n` by one node, and update the values along the way
+    ///
+    /// Returns the counters of the playouts, where the counter at index `k` is for the player
+    /// who moves on turns of parity `k ^ 1`, since a node's value is for the player who moved
+    /// into it.
     fn expand(&mut self, n: usize, turn: i8) -> [u32; 2] {
         let counters = if self.nodes[n].child_count > 0 {
             let node = &self.nodes[n];
+            let policy = self.selection_policy(node.history.rules);
+            let parent = self.node_stats(n);
             // pick child node to traverse into
-            let ln_n = f32::ln(node.count as f32);
-            let mut best_value = 0.0;
+            let mut best_value = f32::NEG_INFINITY;
             let mut best_index = node.first_child;
             for k in node.first_child..(node.first_child + node.child_count) {
-                let child = &self.nodes[k];
-                let value = if child.count > 0 {
-                    child.value as f32 / child.count as f32
-                        + self.temperature_factor * f32::sqrt(ln_n / child.count as f32)
-                } else {
-                    f32::INFINITY
-                };
+                let child = self.node_stats(k);
+                let value = policy.score(&parent, &child, &mut self.pcg);
                 if value > best_value {
                     best_value = value;
                     best_index = k;
                 }
             }
             self.expand(best_index, turn + 1)
+        } else if self.nodes[n].history.is_decided(turn) {
+            // terminal state: win/loss
+            let winner = self.nodes[n].history.rules.quarto_winner(turn);
+            let mut counters = [0; 2];
+            counters[winner ^ 1] += 2 * self.playout_batch_size;
+            counters
+        } else if turn > G::LAST_TURN {
+            // terminal state: draw
+            [self.playout_batch_size; 2]
+        } else if let Some(outcome) = self
+            .tablebase_for(self.nodes[n].history.rules)
+            .and_then(|tablebase| tablebase.probe(&self.nodes[n].history.get_position(turn), turn))
+        {
+            // exact result from the tablebase, for the player to move
+            let mover = turn as usize & 1;
+            let mut counters = [0; 2];
+            match outcome.value {
+                1 => counters[mover ^ 1] += 2 * self.playout_batch_size,
+                -1 => counters[mover] += 2 * self.playout_batch_size,
+                _ => counters = [self.playout_batch_size; 2],
+            }
+            counters
         } else {
-            if self.nodes[n].history.get_position(turn).is_quarto() {
-                // terminal state: win/loss
-                let mut counters = [0; 2];
-                counters[turn as usize & 1] += 2 * self.playout_batch_size;
-                counters
-            } else if turn >= 17 {
-                // terminal state: draw
-                [self.playout_batch_size; 2]
-            } else {
-                // add new children for all legal moves
-                let first_child = self.nodes.len();
-                if turn >= 1 {
-                    if turn < 16 {
-                        for piece_index in turn..16 {
-                            for spot_index in turn - 1..16 {
-                                let mut descendant = self.nodes[n].history.clone();
-                                descendant.swap_pieces(turn, piece_index);
-                                descendant.swap_spots(turn - 1, spot_index);
-                                self.nodes.push(Node {
-                                    value: 0,
-                                    count: 0,
-                                    child_count: 0,
-                                    first_child: 0,
-                                    history: descendant,
-                                });
-                            }
-                        }
-                    } else {
-                        self.nodes.push(Node {
-                            value: 0,
-                            count: 0,
-                            child_count: 0,
-                            first_child: 0,
-                            history: self.nodes[n].history.clone(),
-                        });
-                    }
-                } else {
-                    for piece_index in 0..16 {
-                        let mut descendant = self.nodes[n].history.clone();
-                        descendant.swap_pieces(0, piece_index);
-                        self.nodes.push(Node {
-                            value: 0,
-                            count: 0,
-                            child_count: 0,
-                            first_child: 0,
-                            history: descendant,
-                        });
-                    }
+            // add new children for all legal moves
+            let first_child = self.nodes.len();
+            let history = &self.nodes[n].history;
+            let mut moves: Vec<Move> = history.legal_moves(turn).collect();
+            if history.rules.calling && turn < G::LAST_TURN {
+                // claiming a quarto is the only sensible move on its spot
+                let claim_spots: Vec<Option<Spot>> = moves
+                    .iter()
+                    .filter(|mv| mv.piece.is_none())
+                    .map(|mv| mv.spot)
+                    .collect();
+                moves.retain(|mv| mv.piece.is_none() || !claim_spots.contains(&mv.spot));
+            }
+            let evaluator = self.evaluator_for(history.rules);
+            let priors = match evaluator {
+                Some(evaluator) => evaluator.policy(&history.get_position(turn), &moves),
+                None => vec![1.0 / moves.len() as f32; moves.len()],
+            };
+            // the value of the first child for the player to move there, which is
+            // judged by the evaluator rather than by playouts if the game goes on
+            let value = evaluator.and_then(|evaluator| {
+                let mut first = history.clone();
+                first.apply_move(turn, moves.first()?);
+                let ended = first.is_decided(turn + 1) || turn + 1 > G::LAST_TURN;
+                (!ended).then(|| evaluator.value(&first.get_position(turn + 1)))
+            });
+            for (mv, prior) in moves.iter().zip(priors) {
+                let mut descendant = self.nodes[n].history.clone();
+                descendant.apply_move(turn, mv);
+                self.nodes.push(Node {
+                    value: 0,
+                    count: 0,
+                    child_count: 0,
+                    first_child: 0,
+                    square_sum: 0.0,
+                    prior,
+                    history: descendant,
+                });
+            }
+            let child_count = self.nodes.len() - first_child;
+
+            self.nodes[n].first_child = first_child;
+            self.nodes[n].child_count = child_count;
+
+            let mut counters = [0; 2];
+            // do playouts from the first child, or as many of them as the evaluator leaves
+            let weight = value.map_or(0.0, |_| self.value_weight);
+            let playout_count = ((1.0 - weight) * self.playout_batch_size as f32).round() as u32;
+            if child_count > 0 {
+                if let Some(value) = value {
+                    // the evaluator's share of the batch, as if its value were a playout result
+                    let batch = self.playout_batch_size - playout_count;
+                    let mover = (turn + 1) as usize & 1;
+                    let wins = (2.0 * batch as f32 * value).round() as u32;
+                    counters[mover ^ 1] += wins;
+                    counters[mover] += 2 * batch - wins;
                 }
-                let child_count = self.nodes.len() - first_child;
-
-                self.nodes[n].first_child = first_child;
-                self.nodes[n].child_count = child_count;
-
-                let mut counters = [0; 2];
-                // do playouts from the first child
-                if child_count > 0 {
-                    for _i in 0..self.playout_batch_size {
-                        let result = random_playout(
-                            &mut self.nodes[first_child].history,
-                            turn + 1,
-                            self.pcg.rand_16_fact(),
-                            self.pcg.rand_16_fact(),
-                        );
-                        match result {
-                            Some(final_turn) => {
-                                counters[final_turn as usize & 1] += 2;
-                            }
-                            None => {
-                                counters[0] += 1;
-                                counters[1] += 1;
-                            }
+                self.playouts += playout_count as u64;
+                for _i in 0..playout_count {
+                    let result = random_playout(
+                        &mut self.nodes[first_child].history,
+                        turn + 1,
+                        &mut self.pcg,
+                    );
+                    match result {
+                        Some(winner) => {
+                            counters[winner ^ 1] += 2;
+                        }
+                        None => {
+                            counters[0] += 1;
+                            counters[1] += 1;
                         }
                     }
-                    self.nodes[first_child].value += counters[(turn + 1) as usize & 1];
-                    self.nodes[first_child].count += 2 * self.playout_batch_size;
                 }
-
-                counters
+                let batch_results = (2 * self.playout_batch_size) as f32;
+                let win_rate = counters[(turn + 1) as usize & 1] as f32 / batch_results;
+                self.nodes[first_child].value += counters[(turn + 1) as usize & 1];
+                self.nodes[first_child].count += 2 * self.playout_batch_size;
+                self.nodes[first_child].square_sum += win_rate * win_rate;
             }
+
+            counters
         };
 
+        let batch_results = (2 * self.playout_batch_size) as f32;
+        let win_rate = counters[turn as usize & 1] as f32 / batch_results;
         self.nodes[n].value += counters[turn as usize & 1];
-        self.nodes[n].count += self.playout_batch_size;
+        self.nodes[n].count += 2 * self.playout_batch_size;
+        self.nodes[n].square_sum += win_rate * win_rate;
 
         counters
     }
 }
 
-struct Position {
+/// The part of the time left that a search can use, keeping some for making the move after it
+fn usable_time(time_left: Duration) -> Duration {
+    time_left
+        .mul_f32(0.95)
+        .saturating_sub(Duration::from_millis(20))
+}
+
+#[derive(Clone)]
+struct Position<G: Geometry> {
     // TODO(mkovaxx): use a more redundant encoding that does less work per move to detect a quarto
-    board_pieces: u64,
-    board_mask: u64,
+    board_pieces: G::Bits,
+    board_mask: G::Bits,
     selected_piece: Option<Piece>,
+    rules: Rules,
 }
 
-impl Position {
-    fn new() -> Self {
+impl<G: Geometry> Position<G> {
+    fn new(rules: Rules) -> Self {
         Self {
             selected_piece: None,
-            board_pieces: 0,
-            board_mask: 0,
+            board_pieces: G::Bits::ZERO,
+            board_mask: G::Bits::ZERO,
+            rules,
         }
     }
 
     fn get_piece(&self, spot: Spot) -> Option<Piece> {
-        if (self.board_mask >> (4 * spot.0)) & 0xF != 0 {
-            Some(Piece((self.board_pieces >> (4 * spot.0)) as i8 & 0xF))
+        let shift = G::ATTRIBUTES * spot.0;
+        if (self.board_mask >> shift).to_i8() & (G::PIECES - 1) != 0 {
+            Some(Piece(
+                (self.board_pieces >> shift).to_i8() & (G::PIECES - 1),
+            ))
         } else {
             None
         }
     }
 
     fn place_piece(&mut self, spot: Spot, piece: Piece) {
-        self.board_mask |= (0xF as u64) << (4 * spot.0);
-        self.board_pieces |= (piece.0 as u64) << (4 * spot.0);
+        let shift = G::ATTRIBUTES * spot.0;
+        self.board_mask |= G::Bits::from_i8(G::PIECES - 1) << shift;
+        self.board_pieces |= G::Bits::from_i8(piece.0) << shift;
     }
 
     fn get_chosen_piece(&self) -> Option<Piece> {
@@ -424,44 +2303,129 @@ impl Position {
         self.selected_piece = piece;
     }
 
-    fn is_quarto(&self) -> bool {
-        let group_masks = [
-            // rows
-            0x0000_0000_0000_FFFF,
-            0x0000_0000_FFFF_0000,
-            0x0000_FFFF_0000_0000,
-            0xFFFF_0000_0000_0000,
-            // columns
-            0x000F_000F_000F_000F,
-            0x00F0_00F0_00F0_00F0,
-            0x0F00_0F00_0F00_0F00,
-            0xF000_F000_F000_F000,
-            // diagonals
-            0xF000_0F00_00F0_000F,
-            0x000F_00F0_0F00_F000,
-        ];
+    fn free_spots(&self) -> Vec<Spot> {
+        (0..G::SPOTS)
+            .map(Spot)
+            .filter(|spot| self.get_piece(*spot).is_none())
+            .collect()
+    }
 
-        let attrib_masks = [
-            0x1111_1111_1111_1111,
-            0x2222_2222_2222_2222,
-            0x4444_4444_4444_4444,
-            0x8888_8888_8888_8888,
-        ];
+    /// The pieces that are neither on the board nor chosen
+    fn free_pieces(&self) -> Vec<Piece> {
+        let mut used = 0_u64;
+        for spot in (0..G::SPOTS).map(Spot) {
+            if let Some(piece) = self.get_piece(spot) {
+                used |= 1 << piece.0;
+            }
+        }
+        if let Some(piece) = self.selected_piece {
+            used |= 1 << piece.0;
+        }
+        (0..G::PIECES)
+            .map(Piece)
+            .filter(|piece| used & 1 << piece.0 == 0)
+            .collect()
+    }
 
-        for group_mask in group_masks {
-            if self.board_mask & group_mask != group_mask {
-                continue;
+    /// The legal moves on `turn` from this position, which isn't over yet
+    ///
+    /// The first move only gives a piece, and the last one only places one. A move that ends the
+    /// game with a quarto gives no piece. When quartos have to be called, a move that gives no
+    /// piece claims one, either its own or the one completed on `previous_spot` by the previous
+    /// move, and a quarto can also be left unclaimed by giving a piece. The moves that end the
+    /// game come first.
+    fn legal_moves(&self, turn: i8, previous_spot: Option<Spot>) -> Vec<Move> {
+        let mut moves = vec![];
+        let pieces = if turn < G::LAST_TURN {
+            self.free_pieces()
+        } else {
+            vec![]
+        };
+        if turn == 0 {
+            for piece in pieces {
+                moves.push(Move {
+                    spot: None,
+                    piece: Some(piece),
+                });
+            }
+            return moves;
+        }
+
+        let chosen = self.get_chosen_piece().unwrap();
+        // the spots to place the chosen piece on, and whether that completes a quarto,
+        // or lets the player claim the one their opponent completed
+        let placements: Vec<(Spot, bool)> = self
+            .free_spots()
+            .into_iter()
+            .map(|spot| {
+                let mut after = self.clone();
+                after.place_piece(spot, chosen);
+                let claims_previous = self.rules.calling
+                    && previous_spot.map_or(false, |previous| after.is_quarto_at(previous));
+                (spot, after.is_quarto_at(spot) || claims_previous)
+            })
+            .collect();
+        for (spot, quarto) in &placements {
+            if *quarto || pieces.is_empty() {
+                moves.push(Move {
+                    spot: Some(*spot),
+                    piece: None,
+                });
             }
-            let slice = self.board_pieces & group_mask;
-            let not_slice = !self.board_pieces & group_mask;
-            for attrib_mask in attrib_masks {
-                if slice & attrib_mask == 0 || not_slice & attrib_mask == 0 {
-                    return true;
+        }
+        for (spot, quarto) in &placements {
+            for &piece in &pieces {
+                if !quarto || self.rules.calling {
+                    moves.push(Move {
+                        spot: Some(*spot),
+                        piece: Some(piece),
+                    });
                 }
             }
         }
+        moves
+    }
+
+    /// The position after `mv`, which places the chosen piece and chooses the next one
+    fn after_move(&self, mv: &Move) -> Self {
+        let mut next = self.clone();
+        if let (Some(spot), Some(piece)) = (mv.spot, self.get_chosen_piece()) {
+            next.place_piece(spot, piece);
+        }
+        next.choose_piece(mv.piece);
+        next
+    }
+
+    fn is_quarto(&self) -> bool {
+        for &group_mask in G::group_masks(self.rules.squares) {
+            if self.is_group_quarto(group_mask) {
+                return true;
+            }
+        }
+        false
+    }
 
-        return false;
+    /// Check for a quarto among the groups that include `spot`
+    fn is_quarto_at(&self, spot: Spot) -> bool {
+        let spot_mask = G::Bits::from_i8(G::PIECES - 1) << (G::ATTRIBUTES * spot.0);
+        G::group_masks(self.rules.squares)
+            .iter()
+            .filter(|group_mask| **group_mask & spot_mask != G::Bits::ZERO)
+            .any(|group_mask| self.is_group_quarto(*group_mask))
+    }
+
+    fn is_group_quarto(&self, group_mask: G::Bits) -> bool {
+        if self.board_mask & group_mask != group_mask {
+            return false;
+        }
+        let slice = self.board_pieces & group_mask;
+        let not_slice = !self.board_pieces & group_mask;
+        for &attrib_mask in G::attribute_masks() {
+            if slice & attrib_mask == G::Bits::ZERO || not_slice & attrib_mask == G::Bits::ZERO {
+                return true;
+            }
+        }
+        false
     }
 
     /// Print the position
@@ -476,36 +2440,46 @@ impl Position {
     ///
     /// The position is shown as slices laid out side-by-side, one for each property.
     /// Each spot on the board is identified by a row and a column label.
-    /// Row labels are a..d, and column labels are 1..4.
+    /// Row labels are a..d, and column labels are 1..4 on the standard board.
     ///
     /// Empty spots are shown as dots.
     ///
-    /// A quarto is shown as a * in the top-left corner.
+    /// A quarto is shown as a * in the top-left corner, unless quartos have to be called.
     ///
     fn print(&self, writer: &mut dyn io::Write) -> Result<(), io::Error> {
-        let row_headers = ['a', 'b', 'c', 'd'];
+        let side = G::SIDE as usize;
+        let row_headers = &ROW_SYMBOLS[..side];
+        let col_headers = &COL_SYMBOLS[..side];
 
-        let top_left = if self.is_quarto() { '*' } else { ' ' };
+        let top_left = if !self.rules.calling && self.is_quarto() {
+            '*'
+        } else {
+            ' '
+        };
         write!(writer, " {} ", top_left)?;
-        for _p in 0..4 {
-            write!(writer, "  1 2 3 4")?;
+        for _p in 0..G::ATTRIBUTES {
+            write!(writer, " ")?;
+            for col_header in col_headers {
+                write!(writer, " {}", col_header)?;
+            }
         }
         writeln!(writer)?;
 
         writeln!(writer)?;
-        for r in 0..4 {
-            let mut row = [['.'; 4]; 4];
-            for c in 0..4 {
-                let spot = Spot::from_row_col(r as i8, c as i8);
-                row[c] = option_piece_to_chars(&self.get_piece(spot));
-            }
-
-            for p in 0..4 {
+        for (r, row_header) in row_headers.iter().enumerate() {
+            let row: Vec<Vec<char>> = (0..side)
+                .map(|c| {
+                    let spot = Spot::from_row_col::<G>(r as i8, c as i8);
+                    option_piece_to_chars::<G>(&self.get_piece(spot))
+                })
+                .collect();
+
+            for p in 0..G::ATTRIBUTES as usize {
                 if p == 0 {
-                    write!(writer, " {}  ", row_headers[r])?;
+                    write!(writer, " {}  ", row_header)?;
                 }
-                for c in 0..4 {
-                    write!(writer, " {}", row[c][p])?;
+                for chars in &row {
+                    write!(writer, " {}", chars[p])?;
                 }
                 write!(writer, " ")?;
             }
@@ -516,96 +2490,147 @@ impl Position {
 }
 
 #[derive(Debug, Clone)]
-struct History {
-    pieces_permut: [i8; 16],
-    spots_permut: [i8; 16],
+struct History<G: Geometry> {
+    pieces_permut: G::Pieces,
+    spots_permut: G::Spots,
+    rules: Rules,
+    /// The turn on which a quarto was claimed, when quartos have to be called
+    claim_turn: Option<i8>,
 }
 
-impl History {
-    fn new() -> Self {
+impl<G: Geometry> History<G> {
+    fn new(rules: Rules) -> Self {
         Self {
-            pieces_permut: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
-            spots_permut: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+            pieces_permut: identity(),
+            spots_permut: identity(),
+            rules,
+            claim_turn: None,
         }
     }
 
-    fn try_move(&mut self, turn: i8, mv: &Move) -> Result<(), ()> {
-        let mut temp = self.clone();
-
+    /// Make a move on `turn` that's known to be legal
+    fn apply_move(&mut self, turn: i8, mv: &Move) {
         if let Some(spot) = mv.spot {
-            if !(turn >= 1 && turn <= 16) {
-                return Err(());
+            let spot_index = (turn - 1..G::SPOTS)
+                .find(|i| self.get_raw_spot(*i) == spot)
+                .unwrap();
+            self.swap_spots(turn - 1, spot_index);
+        }
+        match mv.piece {
+            Some(piece) => {
+                let piece_index = (turn..G::PIECES)
+                    .find(|i| self.get_raw_piece(*i) == piece)
+                    .unwrap();
+                self.swap_pieces(turn, piece_index);
             }
-            if let Some(spot_index) = (turn - 1..16).find(|i| self.get_raw_spot(*i) == spot) {
-                temp.swap_spots(turn - 1, spot_index as i8);
-            } else {
-                return Err(());
+            None if self.rules.calling && turn >= 1 && self.can_claim(turn) => {
+                // giving no piece claims the quarto
+                self.claim_turn = Some(turn);
             }
+            None => {}
+        }
+    }
+
+    /// The legal moves on `turn`, none if the game is over
+    fn legal_moves(&self, turn: i8) -> impl Iterator<Item = Move> {
+        if !(0..=G::LAST_TURN).contains(&turn) || self.is_decided(turn) {
+            return vec![].into_iter();
+        }
+        let previous_spot = if turn >= 1 {
+            self.get_spot(turn - 1)
         } else {
-            if turn >= 1 && turn <= 16 {
-                return Err(());
-            }
+            None
+        };
+        self.get_position(turn)
+            .legal_moves(turn, previous_spot)
+            .into_iter()
+    }
+
+    /// Check whether the move of the previous turn ended the game with a quarto
+    ///
+    /// Whether that's a win or a loss for the player who made it depends on the rules.
+    fn is_decided(&self, turn: i8) -> bool {
+        if self.rules.calling {
+            self.claim_turn == Some(turn - 1)
+        } else {
+            self.get_position(turn).is_quarto()
         }
+    }
 
-        if let Some(piece) = mv.piece {
-            if !(turn >= 0 && turn <= 15) {
-                return Err(());
-            }
-            if let Some(piece_index) = (turn..16).find(|i| self.get_raw_piece(*i) == piece) {
-                temp.swap_pieces(turn, piece_index as i8);
-            } else {
-                return Err(());
-            }
+    /// The result of the game after the moves before `turn`
+    fn result(&self, turn: i8) -> GameResult {
+        if self.is_decided(turn) {
+            GameResult::Win(self.rules.quarto_winner(turn))
+        } else if turn > G::LAST_TURN {
+            GameResult::Draw
         } else {
-            if turn >= 0 && turn <= 15 && !temp.get_position(turn + 1).is_quarto() {
-                return Err(());
-            }
+            GameResult::InProgress
         }
+    }
 
-        *self = temp;
-        Ok(())
+    /// Check whether the player of `turn` may claim a quarto after placing their piece
+    ///
+    /// That's the case if the piece they placed completes a quarto, or the piece their opponent
+    /// placed on the previous turn did.
+    fn can_claim(&self, turn: i8) -> bool {
+        let position = self.get_position(turn + 1);
+        [self.get_spot(turn), self.get_spot(turn - 1)]
+            .iter()
+            .flatten()
+            .any(|spot| position.is_quarto_at(*spot))
+    }
+
+    /// The move made on `turn`, where no piece is given if it ended the game with a quarto
+    fn get_move(&self, turn: i8) -> Move {
+        let piece = if self.is_decided(turn + 1) {
+            None
+        } else {
+            self.get_piece(turn)
+        };
+        Move {
+            spot: self.get_spot(turn),
+            piece,
+        }
     }
 
     fn get_piece(&self, turn: i8) -> Option<Piece> {
-        if turn >= 0 && turn <= 15 {
-            Some(Piece(self.pieces_permut[turn as usize]))
+        if (0..G::LAST_TURN).contains(&turn) {
+            Some(Piece(self.pieces_permut.as_ref()[turn as usize]))
         } else {
             None
         }
     }
 
     fn get_raw_piece(&self, index: i8) -> Piece {
-        Piece(self.pieces_permut[index as usize])
+        Piece(self.pieces_permut.as_ref()[index as usize])
     }
 
     fn swap_pieces(&mut self, index_0: i8, index_1: i8) {
-        let piece_0 = self.pieces_permut[index_0 as usize];
-        let piece_1 = self.pieces_permut[index_1 as usize];
-        self.pieces_permut[index_0 as usize] = piece_1;
-        self.pieces_permut[index_1 as usize] = piece_0;
+        self.pieces_permut
+            .as_mut()
+            .swap(index_0 as usize, index_1 as usize);
     }
 
     fn get_spot(&self, turn: i8) -> Option<Spot> {
-        if turn >= 1 && turn <= 16 {
-            Some(Spot(self.spots_permut[turn as usize - 1]))
+        if (1..=G::LAST_TURN).contains(&turn) {
+            Some(Spot(self.spots_permut.as_ref()[turn as usize - 1]))
         } else {
             None
         }
     }
 
     fn get_raw_spot(&self, index: i8) -> Spot {
-        Spot(self.spots_permut[index as usize])
+        Spot(self.spots_permut.as_ref()[index as usize])
     }
 
     fn swap_spots(&mut self, index_0: i8, index_1: i8) {
-        let spot_0 = self.spots_permut[index_0 as usize];
-        let spot_1 = self.spots_permut[index_1 as usize];
-        self.spots_permut[index_0 as usize] = spot_1;
-        self.spots_permut[index_1 as usize] = spot_0;
+        self.spots_permut
+            .as_mut()
+            .swap(index_0 as usize, index_1 as usize);
     }
 
-    fn get_position(&self, turn: i8) -> Position {
-        let mut pos = Position::new();
+    fn get_position(&self, turn: i8) -> Position<G> {
+        let mut pos = Position::new(self.rules);
 
         for i in 0..turn {
             if let (Some(piece), Some(spot)) = (self.get_piece(i - 1), self.get_spot(i)) {
@@ -619,17 +2644,19 @@ impl History {
 
     fn print_free_pieces(&self, turn: i8, writer: &mut dyn io::Write) -> Result<(), io::Error> {
         write!(writer, " ")?;
-        if turn >= 1 && turn <= 16 {
+        if (1..=G::LAST_TURN).contains(&turn) {
             write!(writer, "[")?;
-            for c in piece_to_chars(&self.get_raw_piece(turn - 1)) {
+            for c in piece_to_chars::<G>(&self.get_raw_piece(turn - 1)) {
                 write!(writer, "{}", c)?;
             }
             write!(writer, "]")?;
         }
-        for i in turn..16 {
-            write!(writer, " ")?;
-            for c in piece_to_chars(&self.get_raw_piece(i)) {
-                write!(writer, "{}", c)?;
+        if turn < G::LAST_TURN {
+            for i in turn..G::PIECES {
+                write!(writer, " ")?;
+                for c in piece_to_chars::<G>(&self.get_raw_piece(i)) {
+                    write!(writer, "{}", c)?;
+                }
             }
         }
         writeln!(writer)?;
@@ -637,56 +2664,215 @@ impl History {
     }
 }
 
-fn random_playout(
-    history: &mut History,
+/// A game, with the moves made so far and the state they lead to
+///
+/// It keeps the turn in step with the history, so that callers don't have to.
+#[derive(Clone)]
+struct Game<G: Geometry> {
+    history: History<G>,
+    /// The turn of the player to move
     turn: i8,
-    mut piece_random_source: u64,
-    mut spot_random_source: u64,
-) -> Option<i8> {
-    for i in turn..=17 {
-        // TODO(mkovaxx): optimize away unpacking the history by updating the position with just the next move
-        let position = history.get_position(i);
+    /// The position on `turn`
+    position: Position<G>,
+    result: GameResult,
+}
+
+impl<G: Geometry> Game<G> {
+    /// Start a game where pieces and spots are listed in the order of `history`
+    fn new(history: History<G>) -> Self {
+        Self {
+            position: history.get_position(0),
+            history,
+            turn: 0,
+            result: GameResult::InProgress,
+        }
+    }
+
+    fn history(&self) -> &History<G> {
+        &self.history
+    }
+
+    fn turn(&self) -> i8 {
+        self.turn
+    }
+
+    fn position(&self) -> &Position<G> {
+        &self.position
+    }
+
+    fn rules(&self) -> Rules {
+        self.history.rules
+    }
+
+    /// Change the rules, which is only allowed before the first move
+    fn set_rules(&mut self, rules: Rules) -> Result<(), ()> {
+        if self.turn > 0 {
+            return Err(());
+        }
+        self.history.rules = rules;
+        self.position = self.history.get_position(0);
+        Ok(())
+    }
+
+    /// The index of the player to move, where player 0 moves first
+    fn side_to_move(&self) -> usize {
+        self.turn as usize & 1
+    }
+
+    fn outcome(&self) -> GameResult {
+        self.result
+    }
+
+    /// The legal moves of the player to move, none if the game is over
+    fn legal_moves(&self) -> impl Iterator<Item = Move> {
+        let over = self.result != GameResult::InProgress;
+        self.history.legal_moves(self.turn).filter(move |_| !over)
+    }
 
-        /*
-        position.print(&mut std::io::stdout()).unwrap();
-        println!();
-        */
+    /// Make a move of the player to move, if it's legal
+    fn apply(&mut self, mv: &Move) -> Result<(), MoveError<G>> {
+        if !self.legal_moves().any(|legal| legal == *mv) {
+            return Err(self.move_error(mv).into());
+        }
+        self.apply_legal(mv);
+        Ok(())
+    }
+
+    /// Tell why an illegal move isn't legal
+    fn move_error(&self, mv: &Move) -> MoveErrorKind {
+        if self.result != GameResult::InProgress {
+            return MoveErrorKind::GameFinished;
+        }
+        match mv.spot {
+            Some(spot) if self.turn == 0 => return MoveErrorKind::SpotOnFirstTurn(spot),
+            Some(spot) if self.position.get_piece(spot).is_some() => {
+                return MoveErrorKind::SpotOccupied(spot);
+            }
+            None if self.turn > 0 => return MoveErrorKind::MissingSpot,
+            _ => {}
+        }
+        // with a legal spot, what's left is the piece
+        match mv.piece {
+            Some(piece) if !self.position.free_pieces().contains(&piece) => {
+                MoveErrorKind::PieceUsed(piece)
+            }
+            Some(piece) => MoveErrorKind::PieceAfterQuarto(piece),
+            None => MoveErrorKind::MissingPiece(mv.spot),
+        }
+    }
+
+    /// Make a move that's known to be legal
+    fn apply_legal(&mut self, mv: &Move) {
+        self.history.apply_move(self.turn, mv);
+        self.turn += 1;
+        self.position = self.history.get_position(self.turn);
+        self.result = self.history.result(self.turn);
+    }
+
+    /// Take back the last move, which also takes back a resignation, timeout or agreed draw
+    ///
+    /// Returns the move, or None at the start of the game.
+    fn undo(&mut self) -> Option<Move> {
+        if self.turn == 0 {
+            return None;
+        }
+        let mv = self.history.get_move(self.turn - 1);
+        self.turn -= 1;
+        if self.history.claim_turn == Some(self.turn) {
+            self.history.claim_turn = None;
+        }
+        self.position = self.history.get_position(self.turn);
+        self.result = self.history.result(self.turn);
+        Some(mv)
+    }
+
+    /// End the game in a way that doesn't come from the moves, like a resignation
+    fn end(&mut self, result: GameResult) {
+        self.result = result;
+    }
+}
+
+/// Pieces or spots in their natural order
+fn identity<P: Permutation>() -> P {
+    let mut permut = P::default();
+    for (i, element) in permut.as_mut().iter_mut().enumerate() {
+        *element = i as i8;
+    }
+    permut
+}
+
+/// Count the sequences of `depth` legal moves from the current position, to check the move
+/// generator
+///
+/// A game that ends before `depth` moves isn't counted.
+fn perft<G: Geometry>(game: &mut Game<G>, depth: u32) -> u64 {
+    match depth {
+        0 => 1,
+        1 => game.legal_moves().count() as u64,
+        _ => {
+            let moves: Vec<Move> = game.legal_moves().collect();
+            let mut nodes = 0;
+            for mv in moves {
+                game.apply_legal(&mv);
+                nodes += perft(game, depth - 1);
+                game.undo();
+            }
+            nodes
+        }
+    }
+}
 
-        if position.is_quarto() {
-            return Some(i);
+/// Play random moves until the end of the game
+///
+/// Returns the winner, as the parity of the turns they move on, or None for a draw.
+fn random_playout<G: Geometry>(history: &mut History<G>, turn: i8, pcg: &mut Pcg) -> Option<usize> {
+    let mut piece_random_source = pcg.rand_16_fact();
+    let mut spot_random_source = pcg.rand_16_fact();
+    for i in turn..=G::LAST_TURN + 1 {
+        // TODO(mkovaxx): optimize away unpacking the history by updating the position with just the next move
+        if history.is_decided(i) {
+            return Some(history.rules.quarto_winner(i));
         }
-        if i <= 15 {
+        if i < G::LAST_TURN {
             // pick and commit piece
-            let free_piece_count = 16 - i as u64;
+            if G::PIECES > 16 {
+                // a random number from [0, 16!) only has enough choices for up to 16 pieces
+                piece_random_source = pcg.rand_16_fact();
+            }
+            let free_piece_count = (G::PIECES - i) as u64;
             let piece_index = (piece_random_source % free_piece_count) as i8;
             piece_random_source /= free_piece_count;
             history.swap_pieces(i, i + piece_index);
         }
-        if i >= 1 && i <= 16 {
+        if (1..=G::LAST_TURN).contains(&i) {
             // pick and commit spot
-            let free_spot_count = 17 - i as u64;
+            if G::SPOTS > 16 {
+                spot_random_source = pcg.rand_16_fact();
+            }
+            let free_spot_count = (G::SPOTS + 1 - i) as u64;
             let spot_index = (spot_random_source % free_spot_count) as i8;
             spot_random_source /= free_spot_count;
             history.swap_spots(i - 1, i - 1 + spot_index);
+
+            // players always claim a quarto when quartos have to be called
+            if history.rules.calling && history.can_claim(i) {
+                return Some(history.rules.quarto_winner(i + 1));
+            }
         }
     }
 
     None
 }
 
-fn random_history(mut piece_random_source: u64, mut spot_random_source: u64) -> History {
-    let mut history = History::new();
-    for i in 0..16 {
-        // pick and commit piece
-        let free_piece_count = 16 - i as u64;
-        let piece_index = (piece_random_source % free_piece_count) as i8;
-        piece_random_source /= free_piece_count;
+/// Shuffle the order of pieces and spots for a new game
+fn random_history<G: Geometry>(rules: Rules, pcg: &mut Pcg) -> History<G> {
+    let mut history = History::new(rules);
+    for i in 0..G::PIECES {
+        let piece_index = (pcg.next_u64() % (G::PIECES - i) as u64) as i8;
         history.swap_pieces(i, i + piece_index);
-
-        // pick and commit spot
-        let free_spot_count = 16 - i as u64;
-        let spot_index = (spot_random_source % free_spot_count) as i8;
-        spot_random_source /= free_spot_count;
+    }
+    for i in 0..G::SPOTS {
+        let spot_index = (pcg.next_u64() % (G::SPOTS - i) as u64) as i8;
         history.swap_spots(i, i + spot_index);
     }
     history
@@ -695,19 +2881,17 @@ fn random_history(mut piece_random_source: u64, mut spot_random_source: u64) ->
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct Piece(i8);
 
-fn piece_to_chars(piece: &Piece) -> [char; 4] {
-    let mut chars = ['.'; 4];
+fn piece_to_chars<G: Geometry>(piece: &Piece) -> Vec<char> {
     const SYMBOLS: [char; 2] = ['o', 'x'];
-    for p in 0..4 {
-        chars[p] = SYMBOLS[(piece.0 >> p) as usize & 1];
-    }
-    chars
+    (0..G::ATTRIBUTES)
+        .map(|p| SYMBOLS[(piece.0 >> p) as usize & 1])
+        .collect()
 }
 
-fn option_piece_to_chars(option_piece: &Option<Piece>) -> [char; 4] {
+fn option_piece_to_chars<G: Geometry>(option_piece: &Option<Piece>) -> Vec<char> {
     match option_piece {
-        Some(piece) => piece_to_chars(piece),
-        None => ['.'; 4],
+        Some(piece) => piece_to_chars::<G>(piece),
+        None => vec!['.'; G::ATTRIBUTES as usize],
     }
 }
 
@@ -715,23 +2899,25 @@ fn option_piece_to_chars(option_piece: &Option<Piece>) -> [char; 4] {
 struct Spot(i8);
 
 impl Spot {
-    fn from_row_col(row: i8, col: i8) -> Spot {
-        Spot(col | (row << 2))
+    fn from_row_col<G: Geometry>(row: i8, col: i8) -> Spot {
+        Spot(row * G::SIDE + col)
     }
 }
 
-fn spot_to_chars(spot: &Spot) -> [char; 2] {
-    const ROW_SYMBOLS: [char; 4] = ['a', 'b', 'c', 'd'];
-    const COL_SYMBOLS: [char; 4] = ['1', '2', '3', '4'];
+/// Labels of the rows and columns, of which a board uses as many as its side
+const ROW_SYMBOLS: [char; 9] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i'];
+const COL_SYMBOLS: [char; 9] = ['1', '2', '3', '4', '5', '6', '7', '8', '9'];
+
+fn spot_to_chars<G: Geometry>(spot: &Spot) -> [char; 2] {
     [
-        ROW_SYMBOLS[(spot.0 >> 2) as usize & 3],
-        COL_SYMBOLS[spot.0 as usize & 3],
+        ROW_SYMBOLS[(spot.0 / G::SIDE) as usize],
+        COL_SYMBOLS[(spot.0 % G::SIDE) as usize],
     ]
 }
 
-fn option_spot_to_chars(option_spot: &Option<Spot>) -> [char; 2] {
+fn option_spot_to_chars<G: Geometry>(option_spot: &Option<Spot>) -> [char; 2] {
     match option_spot {
-        Some(spot) => spot_to_chars(spot),
+        Some(spot) => spot_to_chars::<G>(spot),
         None => ['.'; 2],
     }
 }
@@ -771,8 +2957,12 @@ impl Pcg {
         let time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap();
+        Pcg::with_seed(time.as_nanos() as u64)
+    }
+
+    fn with_seed(seed: u64) -> Pcg {
         Pcg {
-            state: time.as_nanos() as u64,
+            state: seed,
             inc: INIT_INC,
         }
     }
@@ -788,6 +2978,11 @@ impl Pcg {
         (xor_shifted >> rot as u64) | (xor_shifted << ((-rot) & 31))
     }
 
+    /// Generate a uniform random number from [0, 1)
+    fn rand_f32(&mut self) -> f32 {
+        (self.next_u64() & 0xFF_FFFF) as f32 / (1 << 24) as f32
+    }
+
     /// Generate a uniform random number from [0, 16!)
     fn rand_16_fact(&mut self) -> u64 {
         loop {
@@ -798,3 +2993,132 @@ impl Pcg {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ponder_stops_before_the_counts_overflow() {
+        let mut bruto = Bruto::<Dims<3, 3>>::new();
+        let game = Game::new(History::new(Rules::standard()));
+        bruto.set_root(&game);
+        bruto.nodes[0].count = u32::MAX - 6 * bruto.playout_batch_size;
+        let stop = AtomicBool::new(false);
+        bruto.ponder(&game, &mut SearchControl::new(&stop));
+        assert!(!bruto.has_room());
+        assert!(bruto.nodes.len() < bruto.ponder_node_limit);
+    }
+
+    #[test]
+    fn options_report_the_current_and_default_values() {
+        let bruto = Bruto::<Dims<4, 4>>::with_level(3).unwrap();
+        let iterations = bruto
+            .get_options()
+            .into_iter()
+            .find(|option| option.name == "iterations")
+            .unwrap();
+        assert!(matches!(
+            iterations.kind,
+            OptionKind::Int {
+                value: 5,
+                default: 1000,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn ladder_needs_a_clear_win() {
+        let score = |wins, draws, losses| MatchScore {
+            wins,
+            draws,
+            losses,
+        };
+        assert!(!score(3, 0, 3).is_clear_win());
+        assert!(!score(2, 2, 2).is_clear_win());
+        assert!(!score(50, 2, 48).is_clear_win());
+        assert!(score(4, 0, 2).is_clear_win());
+        assert!(score(55, 0, 45).is_clear_win());
+    }
+
+    #[test]
+    fn timed_search_stops_before_the_counts_overflow() {
+        let mut bruto = Bruto::<Dims<3, 3>>::new();
+        let game = Game::new(History::new(Rules::standard()));
+        bruto.set_root(&game);
+        bruto.nodes[0].count = u32::MAX - 6 * bruto.playout_batch_size;
+        let stop = AtomicBool::new(false);
+        let time = Duration::from_secs(3600);
+        let mut control =
+            SearchControl::new(&stop).with_time(Some((TimeControl::PerMove(time), time)));
+        match bruto.play(&game, &mut control) {
+            Action::Move(mv) => assert!(game.clone().apply(&mv).is_ok()),
+            Action::Resign => panic!("resigned the empty board"),
+        }
+        assert!(!bruto.has_room());
+    }
+
+    #[test]
+    fn perft_counts_the_standard_moves() {
+        let mut game = Game::new(History::<Dims<4, 4>>::new(Rules::standard()));
+        assert_eq!(perft(&mut game, 1), 16);
+        assert_eq!(perft(&mut game, 2), 3840);
+        assert_eq!(perft(&mut game, 3), 806400);
+    }
+
+    #[test]
+    fn stats_are_valid_json() {
+        let mut bruto = Bruto::<Dims<4, 4>>::new();
+        bruto.iterations = 4;
+        let game = Game::new(History::new(Rules::standard()));
+        let stop = AtomicBool::new(false);
+        bruto.play(&game, &mut SearchControl::new(&stop));
+        let stats = bruto.search_stats().unwrap();
+        assert!(stats.children.iter().any(|(_, visits, _)| *visits == 0));
+        let record = print_stats::<Dims<4, 4>>(bruto.get_name(), stats);
+        for key in [
+            "engine",
+            "board",
+            "position",
+            "move",
+            "iterations",
+            "nodes",
+            "depth",
+            "time",
+            "playouts_per_second",
+            "children",
+            "visits",
+            "value",
+        ] {
+            assert!(record.contains(&format!("\"{}\":", key)), "{}", record);
+        }
+        // unvisited moves have no win rate, rather than a NaN that isn't JSON
+        assert!(!record.contains("NaN"), "{}", record);
+        assert!(
+            record.contains("\"visits\":0,\"value\":null}"),
+            "{}",
+            record
+        );
+        let count = |c| record.matches(c).count();
+        assert_eq!(count('{'), count('}'), "{}", record);
+        assert_eq!(count('['), count(']'), "{}", record);
+        assert_eq!(count('"') % 2, 0, "{}", record);
+    }
+
+    #[test]
+    fn max_robust_overtime_stays_within_the_clock() {
+        let bruto = Bruto::<Dims<4, 4>>::new();
+        let time = Duration::from_secs(10);
+        let per_move = bruto.search_time(TimeControl::PerMove(time), time, 5);
+        assert_eq!(per_move, (usable_time(time), usable_time(time)));
+        let increment = TimeControl::Increment {
+            base: time,
+            increment: Duration::from_secs(1),
+        };
+        for time_left in [time, Duration::from_secs(2), Duration::from_millis(100)] {
+            let (budget, limit) = bruto.search_time(increment, time_left, 5);
+            assert!(budget <= limit && limit <= usable_time(time_left));
+        }
+    }
+}
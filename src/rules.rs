@@ -0,0 +1,119 @@
+//! Rule sets of the game
+//!
+//! The official rules come in levels of increasing difficulty:
+//!
+//! 1. a quarto is a full row, column or diagonal of pieces sharing an attribute
+//! 2. any 2×2 square of pieces sharing an attribute is a quarto as well
+//! 3. like level 1, but a quarto only counts if it's called
+//! 4. like level 2, but a quarto only counts if it's called
+//!
+//! When quartos have to be called, a player who completes one must claim it instead of giving
+//! a piece. If they miss it, their opponent can claim it on the next turn, after placing their
+//! own piece. A quarto that neither player claims doesn't count.
+//!
+//! Any of these can be played as misère, where the player who completes a quarto loses.
+//! Quartos aren't called in misère, since nobody would claim their own loss.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    pub squares: Squares,
+    /// A quarto only ends the game if a player claims it
+    pub calling: bool,
+    /// The player who completes a quarto loses instead of winning
+    pub misere: bool,
+}
+
+/// Whether 2×2 squares of pieces sharing an attribute make a quarto as well
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Squares {
+    Off,
+    On,
+    /// Squares also wrap around the edges of the board, as if it were a torus
+    Torus,
+}
+
+impl Rules {
+    pub fn standard() -> Self {
+        Self::level(1).unwrap()
+    }
+
+    /// The rules of an official level from 1 to 4
+    pub fn level(level: u8) -> Option<Self> {
+        let (squares, calling) = match level {
+            1 => (Squares::Off, false),
+            2 => (Squares::On, false),
+            3 => (Squares::Off, true),
+            4 => (Squares::On, true),
+            _ => return None,
+        };
+        Some(Self {
+            squares,
+            calling,
+            misere: false,
+        })
+    }
+
+    /// Parse rules of the form `level <LEVEL> [misere]` or `<SQUARES> [call] [misere]`
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut words = text.split_whitespace();
+        let mut rules = match words.next()? {
+            "level" => Self::level(words.next()?.parse().ok()?)?,
+            squares => Self {
+                squares: parse_squares(squares)?,
+                calling: false,
+                misere: false,
+            },
+        };
+        for word in words {
+            match word {
+                "call" => rules.calling = true,
+                "misere" => rules.misere = true,
+                _ => return None,
+            }
+        }
+        if rules.calling && rules.misere {
+            return None;
+        }
+        Some(rules)
+    }
+
+    /// The player who wins when the move of turn `turn - 1` completes a quarto,
+    /// as the parity of the turns they move on
+    pub fn quarto_winner(&self, turn: i8) -> usize {
+        let mover = (turn - 1) as usize & 1;
+        if self.misere {
+            mover ^ 1
+        } else {
+            mover
+        }
+    }
+}
+
+fn parse_squares(name: &str) -> Option<Squares> {
+    match name {
+        "standard" => Some(Squares::Off),
+        "squares" => Some(Squares::On),
+        "torus" => Some(Squares::Torus),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.squares {
+            Squares::Off => "standard",
+            Squares::On => "squares",
+            Squares::Torus => "torus",
+        };
+        write!(f, "{}", name)?;
+        if self.calling {
+            write!(f, " call")?;
+        }
+        if self.misere {
+            write!(f, " misere")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! Selection policies, which pick the child of a node that Bruto's search goes down into
+//!
+//! Each child gets a score from what the search knows about it so far, and the child with the
+//! highest score is selected. A policy trades off the children that did well against the ones
+//! that were tried too little to tell.
+
+use crate::Pcg;
+
+/// The names of the policies, as taken by Bruto's `selection` option
+///
+/// With `auto`, Bruto uses PUCT when it has an evaluator and UCB1 otherwise.
+pub(crate) const SELECTION_NAMES: &[&str] = &["auto", "ucb1", "ucb1_tuned", "puct", "thompson"];
+
+/// What the search knows about a node
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct NodeStats {
+    /// Mean result for the player who moved into the node, where a draw counts as half a win
+    pub(crate) win_rate: f32,
+    /// Mean of the squared results of the visits
+    pub(crate) square_rate: f32,
+    /// Number of visits
+    pub(crate) visits: f32,
+    /// Twice the number of playouts, with each visit counting as a batch of them
+    pub(crate) count: f32,
+    /// Prior of the move into the node among its siblings
+    pub(crate) prior: f32,
+}
+
+pub(crate) trait SelectionPolicy {
+    /// Score of a child of `parent`, where the child with the highest score is selected
+    fn score(&self, parent: &NodeStats, child: &NodeStats, pcg: &mut Pcg) -> f32;
+}
+
+/// Upper confidence bound, which explores the children that were visited little
+///
+/// The bound is computed over the playout results rather than the visits, as Bruto always did.
+pub(crate) struct Ucb1 {
+    pub(crate) temperature: f32,
+}
+
+impl SelectionPolicy for Ucb1 {
+    fn score(&self, parent: &NodeStats, child: &NodeStats, _pcg: &mut Pcg) -> f32 {
+        if child.count > 0.0 {
+            child.win_rate + self.temperature * f32::sqrt(f32::ln(parent.count) / child.count)
+        } else {
+            f32::INFINITY
+        }
+    }
+}
+
+/// UCB1 with the exploration of each child bounded by the variance of its results
+///
+/// Like UCB1, it counts playout results rather than visits. The variance of a single playout is
+/// estimated from the variance of the visits, each of which averages a batch of playouts.
+pub(crate) struct Ucb1Tuned;
+
+impl SelectionPolicy for Ucb1Tuned {
+    fn score(&self, parent: &NodeStats, child: &NodeStats, _pcg: &mut Pcg) -> f32 {
+        if child.visits > 0.0 {
+            let ln_n = f32::ln(parent.count);
+            let batch = child.count / child.visits;
+            let variance = batch * (child.square_rate - child.win_rate * child.win_rate).max(0.0)
+                + f32::sqrt(2.0 * ln_n / child.count);
+            child.win_rate + f32::sqrt(ln_n / child.count * variance.min(0.25))
+        } else {
+            f32::INFINITY
+        }
+    }
+}
+
+/// Predictor UCB, which explores the children with high priors first
+pub(crate) struct Puct {
+    pub(crate) factor: f32,
+}
+
+impl SelectionPolicy for Puct {
+    fn score(&self, parent: &NodeStats, child: &NodeStats, _pcg: &mut Pcg) -> f32 {
+        // unvisited children count as draws
+        let win_rate = if child.visits > 0.0 {
+            child.win_rate
+        } else {
+            0.5
+        };
+        win_rate + self.factor * child.prior * f32::sqrt(parent.visits) / (1.0 + child.visits)
+    }
+}
+
+/// Thompson sampling, which scores each child by a sample of its win rate from a Beta posterior
+///
+/// The posterior counts the playout results of the visits, where a draw is half a win.
+pub(crate) struct Thompson;
+
+impl SelectionPolicy for Thompson {
+    fn score(&self, _parent: &NodeStats, child: &NodeStats, pcg: &mut Pcg) -> f32 {
+        let n = child.count / 2.0;
+        let wins = n * child.win_rate;
+        sample_beta(1.0 + wins, 1.0 + n - wins, pcg)
+    }
+}
+
+/// One of the policies, picked without an allocation for each node the search goes through
+pub(crate) enum Selection {
+    Ucb1(Ucb1),
+    Ucb1Tuned(Ucb1Tuned),
+    Puct(Puct),
+    Thompson(Thompson),
+}
+
+impl SelectionPolicy for Selection {
+    fn score(&self, parent: &NodeStats, child: &NodeStats, pcg: &mut Pcg) -> f32 {
+        match self {
+            Selection::Ucb1(policy) => policy.score(parent, child, pcg),
+            Selection::Ucb1Tuned(policy) => policy.score(parent, child, pcg),
+            Selection::Puct(policy) => policy.score(parent, child, pcg),
+            Selection::Thompson(policy) => policy.score(parent, child, pcg),
+        }
+    }
+}
+
+/// Sample from the Beta distribution with shapes of at least 1
+fn sample_beta(alpha: f32, beta: f32, pcg: &mut Pcg) -> f32 {
+    let x = sample_gamma(alpha, pcg);
+    let y = sample_gamma(beta, pcg);
+    x / (x + y)
+}
+
+/// Sample from the Gamma distribution with a shape of at least 1, by Marsaglia and Tsang's method
+fn sample_gamma(shape: f32, pcg: &mut Pcg) -> f32 {
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / f32::sqrt(9.0 * d);
+    loop {
+        let x = sample_normal(pcg);
+        let v = 1.0 + c * x;
+        if v <= 0.0 {
+            continue;
+        }
+        let v = v * v * v;
+        let u = 1.0 - pcg.rand_f32();
+        if f32::ln(u) < 0.5 * x * x + d - d * v + d * f32::ln(v) {
+            return d * v;
+        }
+    }
+}
+
+/// Sample from the standard normal distribution, by the Box-Muller transform
+fn sample_normal(pcg: &mut Pcg) -> f32 {
+    let u = 1.0 - pcg.rand_f32();
+    let v = pcg.rand_f32();
+    f32::sqrt(-2.0 * f32::ln(u)) * f32::cos(2.0 * std::f32::consts::PI * v)
+}
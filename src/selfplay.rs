@@ -0,0 +1,323 @@
+//! Self-play data, to train position evaluators
+//!
+//! Bruto plays against itself with noise added to the win rates of the moves it picks, so that
+//! the games cover more positions than its best play would. Every position of a game is
+//! recorded with the visits that the search gave each move, and the result of the game.
+//!
+//! The dataset is a CSV file that starts with a line with the format version, the board, the
+//! rules and the settings of the generator, followed by a line with the names of the columns.
+//! Each other line is a position, with the columns `game`, `turn`, `pieces` and `mask` (the bitboards of the position in
+//! hexadecimal), `selected` (the piece to place, or -1 on the first turn), `result` (1 if the
+//! player to move went on to win, 0 for a draw, and -1 for a loss) and `visits` (the moves of
+//! the search, as space-separated `<MOVE>:<VISITS>` pairs).
+//!
+//! A game is written once it's over, but an interrupted run can still leave the last game cut
+//! short, even at the end of a line where it looks whole. Running the generator again with the
+//! same file removes the last game and plays it again, then goes on after it. Each game is
+//! played with its own random numbers, seeded from `--seed` and the number of the game, so a
+//! resumed run writes the same games as one that wasn't interrupted. A run with another board,
+//! other rules or other settings than the dataset's is rejected, rather than mixing their games.
+
+use crate::{
+    args::Args,
+    geometry::{Board, Dims, Geometry},
+    print_move,
+    rules::Rules,
+    Action, Bruto, Engine, GameResult, Pcg, SearchControl,
+};
+use std::{
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    sync::atomic::AtomicBool,
+    time::Instant,
+};
+
+/// Version of the file format
+const VERSION: u32 = 1;
+
+/// The names of the columns, on the line after the header
+const COLUMNS: &str = "game,turn,pieces,mask,selected,result,visits";
+
+/// Spreads the seeds of consecutive games apart
+const GAME_SEED_STEP: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Run the generator with command line arguments of the form
+/// `--games <N> --iterations <N> --out <FILE> [--noise <X>] [--seed <N>] [--board <BOARD>]
+/// [--rules <RULES>] [<MOVE>...]`
+///
+/// The games start from the position that the moves lead to, and the file ends up with
+/// `--games` games. The noise is Bruto's `move_noise`, 0.1 by default.
+pub fn run(args: &[String], output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(
+        args,
+        &["--games", "--iterations", "--out", "--noise", "--seed"],
+    )?;
+    let flag = |flag: &str| args.flags.get(flag).ok_or(format!("missing `{}`", flag));
+    let invalid = |flag: &str| format!("invalid value of `{}`", flag);
+    let games = flag("--games")?.parse().map_err(|_| invalid("--games"))?;
+    let settings = Settings {
+        iterations: flag("--iterations")?
+            .parse()
+            .map_err(|_| invalid("--iterations"))?,
+        noise: match args.flags.get("--noise") {
+            Some(noise) => noise
+                .parse()
+                .ok()
+                .filter(|noise| (0.0..=1.0).contains(noise))
+                .ok_or_else(|| invalid("--noise"))?,
+            None => 0.1,
+        },
+        seed: match args.flags.get("--seed") {
+            Some(seed) => seed.parse().map_err(|_| invalid("--seed"))?,
+            None => 0,
+        },
+    };
+    let path = *flag("--out")?;
+    match args.board {
+        Board::Small => generate::<Dims<3, 3>>(&args, &settings, games, path, output),
+        Board::Standard => generate::<Dims<4, 4>>(&args, &settings, games, path, output),
+        Board::Large => generate::<Dims<5, 5>>(&args, &settings, games, path, output),
+    }
+}
+
+/// The settings that the games depend on, besides the board and the rules
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Settings {
+    iterations: u32,
+    noise: f32,
+    seed: u64,
+}
+
+/// The header of a dataset, up to the names of the columns
+fn header<G: Geometry>(rules: Rules, settings: &Settings) -> String {
+    format!(
+        "bruto selfplay {} {} {} seed {} noise {} iterations {}\n{}\n",
+        VERSION,
+        G::BOARD,
+        rules,
+        settings.seed,
+        settings.noise,
+        settings.iterations,
+        COLUMNS
+    )
+}
+
+/// Read the header of a dataset for this board, with the rules and settings of its games
+pub(crate) fn read_header<G: Geometry>(text: &str) -> io::Result<(Rules, Settings)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut lines = text.lines();
+    let first = lines.next().unwrap_or_default();
+    if lines.next() != Some(COLUMNS) {
+        return Err(invalid("the dataset has an invalid header"));
+    }
+    let words: Vec<&str> = first.split_whitespace().collect();
+    match words[..] {
+        ["bruto", "selfplay", version, ..] if version != VERSION.to_string() => {
+            Err(invalid("the dataset has an unsupported version"))
+        }
+        ["bruto", "selfplay", _, board, ..] if board != G::BOARD.to_string() => {
+            Err(invalid("the dataset is for another board"))
+        }
+        ["bruto", "selfplay", _, _, ref rules @ .., "seed", seed, "noise", noise, "iterations", iterations] => {
+            match (
+                Rules::parse(&rules.join(" ")),
+                seed.parse(),
+                noise.parse(),
+                iterations.parse(),
+            ) {
+                (Some(rules), Ok(seed), Ok(noise), Ok(iterations)) => Ok((
+                    rules,
+                    Settings {
+                        iterations,
+                        noise,
+                        seed,
+                    },
+                )),
+                _ => Err(invalid("the dataset has an invalid header")),
+            }
+        }
+        _ => Err(invalid("the dataset has an invalid header")),
+    }
+}
+
+fn generate<G: Geometry>(
+    args: &Args,
+    settings: &Settings,
+    games: u32,
+    path: &str,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let start_game = args.replay::<G>()?;
+    let header = header::<G>(args.rules, settings);
+    let resume = resume_point::<G>(path, &header)?;
+    let start = Instant::now();
+    let mut file = OpenOptions::new().create(true).write(true).open(path)?;
+    let done = match resume {
+        Some((done, length)) => {
+            // with all the games there already, the last one stays
+            if done < games {
+                file.set_len(length)?;
+            }
+            done
+        }
+        None => {
+            file.set_len(0)?;
+            write!(file, "{}", header)?;
+            0
+        }
+    };
+    file.seek(SeekFrom::End(0))?;
+    let stop = AtomicBool::new(false);
+    let mut positions = 0;
+    for number in done..games {
+        let mut bruto = Bruto::<G>::new();
+        bruto.iterations = settings.iterations;
+        bruto.move_noise = settings.noise;
+        bruto.pcg = Pcg::with_seed(settings.seed ^ (number as u64).wrapping_mul(GAME_SEED_STEP));
+
+        // the position before each move, with the visits of the moves
+        let mut records = vec![];
+        let mut game = start_game.clone();
+        while game.outcome() == GameResult::InProgress {
+            let mv = match bruto.play(&game, &mut SearchControl::new(&stop)) {
+                Action::Move(mv) => mv,
+                Action::Resign => {
+                    game.end(GameResult::Resignation(game.side_to_move()));
+                    break;
+                }
+            };
+            let visits: Vec<String> = bruto
+                .root_children(game.turn())
+                .iter()
+                .map(|(mv, visits, _)| format!("{}:{}", print_move::<G>(mv), visits))
+                .collect();
+            records.push((game.position().clone(), game.turn(), visits));
+            game.apply(&mv)?;
+        }
+
+        let winner = game.outcome().winner();
+        let mut lines = vec![];
+        for (position, turn, visits) in &records {
+            let result = match winner {
+                Some(winner) if winner == *turn as usize & 1 => 1,
+                Some(_) => -1,
+                None => 0,
+            };
+            let selected = position.get_chosen_piece().map_or(-1, |piece| piece.0);
+            lines.push(format!(
+                "{},{},{:x},{:x},{},{},{}",
+                number,
+                turn,
+                position.board_pieces,
+                position.board_mask,
+                selected,
+                result,
+                visits.join(" ")
+            ));
+        }
+        file.write_all((lines.join("\n") + "\n").as_bytes())?;
+        file.flush()?;
+        positions += records.len();
+    }
+
+    writeln!(output, "games {}", games.saturating_sub(done))?;
+    writeln!(output, "positions {}", positions)?;
+    writeln!(output, "time {:.3}s", start.elapsed().as_secs_f32())?;
+    Ok(())
+}
+
+/// Where to resume the dataset: the number of its last game, which may have been cut short, and
+/// the length of the file before that game, or None if there is no dataset yet
+///
+/// The dataset has to have been generated with the same `header`.
+fn resume_point<G: Geometry>(path: &str, header: &str) -> io::Result<Option<(u32, u64)>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if header.starts_with(&text) {
+        // the header wasn't written in full
+        return Ok(None);
+    }
+    if !text.starts_with(header) {
+        // tell what doesn't match, or that the header is invalid
+        read_header::<G>(&text)?;
+        return Err(invalid(
+            "the dataset was generated with other rules or settings".to_string(),
+        ));
+    }
+    let mut resume = (0, header.len());
+    let mut last_game = None;
+    let mut length = header.len();
+    for (number, line) in text[header.len()..].split_inclusive('\n').enumerate() {
+        if !line.ends_with('\n') {
+            // the last line was cut short
+            break;
+        }
+        let game: u32 = line
+            .split(',')
+            .next()
+            .and_then(|game| game.parse().ok())
+            .ok_or_else(|| invalid(format!("the dataset has an invalid line {}", number + 3)))?;
+        if last_game != Some(game) {
+            resume = (game, length);
+            last_game = Some(game);
+        }
+        length += line.len();
+    }
+    Ok(Some((resume.0, resume.1 as u64)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumed_runs_write_the_same_games() {
+        let path = std::env::temp_dir().join(format!("bruto-selfplay-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let args: Vec<String> = [
+            "--board",
+            "3x3x3",
+            "--games",
+            "3",
+            "--iterations",
+            "20",
+            "--out",
+            path,
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        run(&args, &mut io::sink()).unwrap();
+        let full = fs::read_to_string(path).unwrap();
+        let last_game = full.rfind("\n2,").unwrap() + 1;
+        let last_line = full.trim_end().rfind('\n').unwrap() + 1;
+
+        // cut the file short in the header, in the middle of a line, and at the end of a line
+        for cut in [
+            5,
+            last_line + 3,
+            last_game + 1,
+            last_line,
+            last_game,
+            full.len(),
+        ] {
+            fs::write(path, &full[..cut]).unwrap();
+            run(&args, &mut io::sink()).unwrap();
+            assert_eq!(fs::read_to_string(path).unwrap(), full, "cut at {}", cut);
+        }
+
+        // games of other settings or rules don't go in the same dataset
+        for other in [["--seed", "1"], ["--rules", "squares"]] {
+            let mut other_args = args.clone();
+            other_args.extend(other.iter().map(|arg| arg.to_string()));
+            assert!(run(&other_args, &mut io::sink()).is_err());
+        }
+        assert_eq!(fs::read_to_string(path).unwrap(), full);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+//! Strong solver that finds the game-theoretic value of a position
+//!
+//! It searches depth-first, and keeps the values of solved positions in a transposition store
+//! keyed by canonical position, so that positions which are equivalent under the symmetries of
+//! the game are solved only once. The store can be kept in a file, which is a hash table that's
+//! looked up on disk, so it isn't bounded by the memory of the machine. Every solved position
+//! is written to the file right away, which makes it a checkpoint as well: a solve that was
+//! interrupted resumes from the positions it already solved when it's started again with the
+//! same file.
+//!
+//! Quartos that have to be called aren't supported.
+
+use crate::{
+    args::Args,
+    geometry::{Board, Dims, Geometry},
+    print_move,
+    rules::Rules,
+    symmetry::Symmetries,
+    Move, Position,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    time::Instant,
+};
+
+/// Version of the store file format
+const STORE_VERSION: u32 = 2;
+
+/// Bytes of a slot in the store file, a little-endian key followed by the value plus 2, which
+/// leaves 0 for an empty slot
+const SLOT_SIZE: usize = 17;
+
+/// Slots in a bucket of the store file, which is read at once
+const BUCKET_SLOTS: usize = 8;
+
+const BUCKET_SIZE: usize = SLOT_SIZE * BUCKET_SLOTS;
+
+/// Number of buckets in a new store file
+const INITIAL_BUCKETS: u64 = 1 << 10;
+
+/// Number of values that a store with a file keeps in memory, before it forgets them all
+const CACHE_SIZE: usize = 1 << 20;
+
+/// Positions this close to the end of the game are searched without the store, since that's
+/// faster than finding their canonical key
+const SEARCH_ONLY_TURNS: i8 = 5;
+
+/// Run the solver with command line arguments of the form
+/// `[--board <BOARD>] [--rules <RULES>] [--store <FILE>] [<MOVE>...]`
+///
+/// The moves lead from the start of the game to the position to solve.
+pub fn run(args: &[String], output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["--store"])?;
+    let store_path = args.flags.get("--store").copied();
+    match args.board {
+        Board::Small => solve::<Dims<3, 3>>(&args, store_path, output),
+        Board::Standard => solve::<Dims<4, 4>>(&args, store_path, output),
+        Board::Large => Err("the board is too large for the solver".into()),
+    }
+}
+
+fn solve<G: Geometry>(
+    args: &Args,
+    store_path: Option<&str>,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let game = args.replay::<G>()?;
+    let rules = args.rules;
+
+    let header = format!(
+        "bruto solver store {} {} {}\n",
+        STORE_VERSION,
+        G::BOARD,
+        rules
+    );
+    let mut solver = Solver {
+        rules,
+        symmetries: Some(Symmetries::new(rules.squares)),
+        store: Store::open(store_path, &header)?,
+        nodes: 0,
+    };
+    let start = Instant::now();
+    let (value, best_move) = solver.best_move(game.position(), game.turn())?;
+
+    let name = match value {
+        1 => "win",
+        0 => "draw",
+        _ => "loss",
+    };
+    writeln!(output, "value {} for the player to move", name)?;
+    writeln!(output, "best {}", print_move::<G>(&best_move))?;
+    let symmetries = solver
+        .symmetries
+        .as_ref()
+        .map_or(1, Symmetries::board_count);
+    writeln!(output, "symmetries {}", symmetries)?;
+    writeln!(output, "nodes {}", solver.nodes)?;
+    writeln!(output, "positions {}", solver.store.len())?;
+    writeln!(output, "time {:.3}s", start.elapsed().as_secs_f32())?;
+    Ok(())
+}
+
+struct Solver<G: Geometry> {
+    rules: Rules,
+    /// The symmetries that key the store, or none to search without storing
+    symmetries: Option<Symmetries<G>>,
+    store: Store,
+    /// Number of positions searched, rather than found in the store
+    nodes: u64,
+}
+
+impl<G: Geometry> Solver<G> {
+    /// The value of `position` on `turn` for the player to move: 1 for a win, 0 for a draw,
+    /// and -1 for a loss
+    fn value(&mut self, position: &Position<G>, turn: i8) -> io::Result<i8> {
+        let key = match &self.symmetries {
+            Some(symmetries) if G::LAST_TURN - turn >= SEARCH_ONLY_TURNS => {
+                symmetries.canonical_key(position)
+            }
+            _ => return Ok(self.best_move(position, turn)?.0),
+        };
+        if let Some(value) = self.store.get(key)? {
+            return Ok(value);
+        }
+        let (value, _) = self.best_move(position, turn)?;
+        self.store.insert(key, value)?;
+        Ok(value)
+    }
+
+    /// The value of `position` on `turn` for the player to move, and a move that achieves it
+    fn best_move(&mut self, position: &Position<G>, turn: i8) -> io::Result<(i8, Move)> {
+        self.nodes += 1;
+        let mut best = None;
+        // moves that end the game come first, so a quarto is found before any deeper search
+        for mv in position.legal_moves(turn, None) {
+            let next = position.after_move(&mv);
+            let value = match (mv.spot, mv.piece) {
+                (_, Some(_)) => -self.value(&next, turn + 1)?,
+                (Some(spot), None) if next.is_quarto_at(spot) => {
+                    if self.rules.misere {
+                        -1
+                    } else {
+                        1
+                    }
+                }
+                // placing the last piece without a quarto is a draw
+                (_, None) => 0,
+            };
+            if consider(&mut best, value, mv) {
+                break;
+            }
+        }
+        Ok(best.unwrap())
+    }
+}
+
+/// The value of `position` on `turn` for the player to move, and a move that achieves it,
+/// if it's close enough to the end of the game to be solved right away
+pub(crate) fn solve_endgame<G: Geometry>(
+    position: &Position<G>,
+    turn: i8,
+    rules: Rules,
+) -> Option<(i8, Move)> {
+    if rules.calling
+        || !(0..=G::LAST_TURN).contains(&turn)
+        || G::LAST_TURN - turn >= SEARCH_ONLY_TURNS
+    {
+        return None;
+    }
+    // so close to the end, the search never reaches the store
+    let mut solver = Solver {
+        rules,
+        symmetries: None,
+        store: Store::open(None, "").ok()?,
+        nodes: 0,
+    };
+    solver.best_move(position, turn).ok()
+}
+
+/// Keep the move if it's better than the best so far, and tell whether it's a win
+fn consider(best: &mut Option<(i8, Move)>, value: i8, mv: Move) -> bool {
+    if best
+        .as_ref()
+        .map_or(true, |(best_value, _)| value > *best_value)
+    {
+        *best = Some((value, mv));
+    }
+    value == 1
+}
+
+/// Values of solved positions by canonical key, in memory or in a file
+///
+/// With a file, the values that were used lately are cached in memory.
+struct Store {
+    /// All the values without a file, and the ones used lately with one
+    cache: HashMap<u128, i8>,
+    table: Option<Table>,
+}
+
+impl Store {
+    /// Open the store file at `path`, or create it if it doesn't exist, or keep the store in
+    /// memory without a path
+    fn open(path: Option<&str>, header: &str) -> io::Result<Self> {
+        Ok(Self {
+            cache: HashMap::new(),
+            table: path.map(|path| Table::open(path, header)).transpose()?,
+        })
+    }
+
+    fn get(&mut self, key: u128) -> io::Result<Option<i8>> {
+        if let Some(value) = self.cache.get(&key) {
+            return Ok(Some(*value));
+        }
+        let value = match &mut self.table {
+            Some(table) => table.get(key)?,
+            None => None,
+        };
+        if let Some(value) = value {
+            self.remember(key, value);
+        }
+        Ok(value)
+    }
+
+    fn insert(&mut self, key: u128, value: i8) -> io::Result<()> {
+        if let Some(table) = &mut self.table {
+            table.insert(key, value)?;
+        }
+        self.remember(key, value);
+        Ok(())
+    }
+
+    fn remember(&mut self, key: u128, value: i8) {
+        if self.table.is_some() && self.cache.len() >= CACHE_SIZE {
+            self.cache.clear();
+        }
+        self.cache.insert(key, value);
+    }
+
+    /// Number of values in the store
+    fn len(&self) -> u64 {
+        match &self.table {
+            Some(table) => table.entries,
+            None => self.cache.len() as u64,
+        }
+    }
+}
+
+/// A hash table of values in a file, after a header
+///
+/// The table is made of buckets of slots, and a key goes to the first empty slot from the
+/// bucket of its hash on, so a lookup mostly reads a single bucket. Each value is written as
+/// soon as it's inserted. When the table gets too full, it's copied to a new file with twice the
+/// buckets, which then replaces the old one.
+struct Table {
+    path: String,
+    header: String,
+    file: File,
+    /// Number of buckets, a power of two
+    buckets: u64,
+    /// Number of slots in use
+    entries: u64,
+}
+
+/// Where a key is in a table
+enum Slot {
+    Found(i8),
+    /// The key isn't in the table, and this empty slot is where it goes, as an offset in the
+    /// file
+    Empty(u64),
+}
+
+impl Table {
+    /// Open the table at `path`, or create it if it doesn't exist
+    ///
+    /// A file with a different header belongs to another version, board or rules, and is
+    /// rejected.
+    fn open(path: &str, header: &str) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(header.as_bytes())?;
+            file.set_len(header.len() as u64 + INITIAL_BUCKETS * BUCKET_SIZE as u64)?;
+        }
+        file.seek(SeekFrom::Start(0))?;
+        let mut start = vec![0; header.len()];
+        let size = file.metadata()?.len().saturating_sub(header.len() as u64);
+        if file.read_exact(&mut start).is_err() || start != header.as_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("`{}` isn't a store for this board and rules", path),
+            ));
+        }
+        let buckets = size / BUCKET_SIZE as u64;
+        if size % BUCKET_SIZE as u64 != 0 || !buckets.is_power_of_two() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the store `{}` is damaged", path),
+            ));
+        }
+        let mut table = Self {
+            path: path.to_string(),
+            header: header.to_string(),
+            file,
+            buckets,
+            entries: 0,
+        };
+        let mut entries = 0;
+        table.scan(|_, _| {
+            entries += 1;
+            Ok(())
+        })?;
+        table.entries = entries;
+        Ok(table)
+    }
+
+    fn get(&self, key: u128) -> io::Result<Option<i8>> {
+        Ok(match self.find(key)? {
+            Slot::Found(value) => Some(value),
+            Slot::Empty(_) => None,
+        })
+    }
+
+    fn insert(&mut self, key: u128, value: i8) -> io::Result<()> {
+        // keep a quarter of the slots empty, so that the buckets rarely overflow
+        if 4 * (self.entries + 1) > 3 * self.buckets * BUCKET_SLOTS as u64 {
+            self.grow()?;
+        }
+        if let Slot::Empty(offset) = self.find(key)? {
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&encode_slot(key, value))?;
+            self.entries += 1;
+        }
+        Ok(())
+    }
+
+    fn find(&self, key: u128) -> io::Result<Slot> {
+        // the high bits of the product mix all the bits of the key
+        let hash = (key as u64 ^ (key >> 64) as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let mut bucket = hash >> (64 - self.buckets.trailing_zeros());
+        let mut file = &self.file;
+        let mut data = [0; BUCKET_SIZE];
+        loop {
+            let offset = self.header.len() as u64 + bucket * BUCKET_SIZE as u64;
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut data)?;
+            for (index, slot) in data.chunks_exact(SLOT_SIZE).enumerate() {
+                match decode_slot(slot) {
+                    Some((other, value)) if other == key => return Ok(Slot::Found(value)),
+                    Some(_) => {}
+                    None => return Ok(Slot::Empty(offset + (index * SLOT_SIZE) as u64)),
+                }
+            }
+            // a full bucket overflows into the next one
+            bucket = (bucket + 1) % self.buckets;
+        }
+    }
+
+    /// Call `visit` with the key and value of each slot in use, in the order of the file
+    fn scan(&self, mut visit: impl FnMut(u128, i8) -> io::Result<()>) -> io::Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.header.len() as u64))?;
+        let mut reader = BufReader::new(file);
+        let mut slot = [0; SLOT_SIZE];
+        for _ in 0..self.buckets * BUCKET_SLOTS as u64 {
+            reader.read_exact(&mut slot)?;
+            if let Some((key, value)) = decode_slot(&slot) {
+                visit(key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the values to a table with twice the buckets in a new file, and replace the file
+    /// with it once it's complete, so that an interruption leaves the old one
+    fn grow(&mut self) -> io::Result<()> {
+        let new_path = format!("{}.new", self.path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&new_path)?;
+        file.write_all(self.header.as_bytes())?;
+        let buckets = 2 * self.buckets;
+        file.set_len(self.header.len() as u64 + buckets * BUCKET_SIZE as u64)?;
+        let mut grown = Self {
+            path: self.path.clone(),
+            header: self.header.clone(),
+            file,
+            buckets,
+            entries: 0,
+        };
+        self.scan(|key, value| grown.insert(key, value))?;
+        grown.file.sync_all()?;
+        fs::rename(&new_path, &self.path)?;
+        *self = grown;
+        Ok(())
+    }
+}
+
+fn encode_slot(key: u128, value: i8) -> [u8; SLOT_SIZE] {
+    let mut slot = [0; SLOT_SIZE];
+    slot[..16].copy_from_slice(&key.to_le_bytes());
+    slot[16] = (value + 2) as u8;
+    slot
+}
+
+fn decode_slot(slot: &[u8]) -> Option<(u128, i8)> {
+    match slot[16] {
+        0 => None,
+        stored => Some((
+            u128::from_le_bytes(slot[..16].try_into().unwrap()),
+            stored as i8 - 2,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_file_grows_and_reopens() {
+        let path = std::env::temp_dir().join(format!("bruto-store-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let header = "bruto solver store test\n";
+        let value = |key: u128| (key % 3) as i8 - 1;
+        // keys that differ only in their high bits, like canonical keys with few pieces
+        let keys: Vec<u128> = (1..20_000).map(|i| i << 100).collect();
+        {
+            let mut store = Store::open(Some(path), header).unwrap();
+            for &key in &keys {
+                store.insert(key, value(key)).unwrap();
+            }
+            assert_eq!(store.len(), keys.len() as u64);
+        }
+        let mut store = Store::open(Some(path), header).unwrap();
+        assert_eq!(store.len(), keys.len() as u64);
+        for &key in &keys {
+            assert_eq!(store.get(key).unwrap(), Some(value(key)));
+        }
+        assert_eq!(store.get(0).unwrap(), None);
+        assert!(Store::open(Some(path), "bruto solver store other\n").is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+//! Symmetries of the game, which let equivalent positions be treated as one
+//!
+//! Moving the spots of the board around in a way that maps the groups of a quarto onto each
+//! other doesn't change the game, and neither does permuting or complementing the attributes of
+//! all pieces. On the standard board there are 32 such maps of the spots, and 4! × 2⁴ = 384
+//! maps of the pieces.
+
+use crate::{geometry::Geometry, rules::Squares, BoardBits, Piece, Position, Spot};
+use std::marker::PhantomData;
+
+/// A symmetry of the board, given by where it moves each spot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BoardMap(Vec<i8>);
+
+/// A symmetry of the pieces, given by where it moves each piece
+///
+/// It permutes the attributes, and complements some of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PieceMap(Vec<i8>);
+
+impl Spot {
+    pub(crate) fn transform(self, map: &BoardMap) -> Spot {
+        Spot(map.0[self.0 as usize])
+    }
+}
+
+impl Piece {
+    pub(crate) fn transform(self, map: &PieceMap) -> Piece {
+        Piece(map.0[self.0 as usize])
+    }
+}
+
+impl<G: Geometry> Position<G> {
+    /// The position with the spots moved by `board` and the pieces, including the chosen one,
+    /// moved by `pieces`
+    pub(crate) fn transform(&self, board: &BoardMap, pieces: &PieceMap) -> Self {
+        let mut transformed = Position::new(self.rules);
+        for spot in (0..G::SPOTS).map(Spot) {
+            if let Some(piece) = self.get_piece(spot) {
+                transformed.place_piece(spot.transform(board), piece.transform(pieces));
+            }
+        }
+        transformed.choose_piece(self.get_chosen_piece().map(|piece| piece.transform(pieces)));
+        transformed
+    }
+}
+
+pub(crate) struct Symmetries<G: Geometry> {
+    board_maps: Vec<BoardMap>,
+    /// The symmetries of the pieces, which complement the attributes of a piece by the bits of
+    /// `flip` and then permute them, for each `flip` in turn
+    piece_maps: Vec<PieceMap>,
+    /// The symmetry of the pieces that leaves them where they are
+    identity: PieceMap,
+    geometry: PhantomData<G>,
+}
+
+impl<G: Geometry> Symmetries<G> {
+    /// Find the symmetries of the board under rules with the given `squares`
+    ///
+    /// A symmetry maps rows to rows or columns, so it permutes the rows and the columns,
+    /// and possibly transposes the board. Only those that map every group to a group are kept.
+    pub(crate) fn new(squares: Squares) -> Self {
+        let side = G::SIDE as usize;
+        let groups: Vec<u32> = G::group_masks(squares)
+            .iter()
+            .map(|group_mask| {
+                (0..G::SPOTS)
+                    .filter(|s| {
+                        let spot_mask = G::Bits::from_i8(G::PIECES - 1) << (G::ATTRIBUTES * s);
+                        *group_mask & spot_mask != G::Bits::ZERO
+                    })
+                    .fold(0, |spots, s| spots | 1 << s)
+            })
+            .collect();
+        let mut board_maps = vec![];
+        for row_map in permutations(side) {
+            for col_map in permutations(side) {
+                for transpose in [false, true] {
+                    let spot_map: Vec<i8> = (0..G::SPOTS)
+                        .map(|s| {
+                            let (row, col) = (
+                                row_map[(s / G::SIDE) as usize],
+                                col_map[(s % G::SIDE) as usize],
+                            );
+                            if transpose {
+                                col * G::SIDE + row
+                            } else {
+                                row * G::SIDE + col
+                            }
+                        })
+                        .collect();
+                    let maps_groups = groups.iter().all(|group| {
+                        let mapped = (0..G::SPOTS)
+                            .filter(|s| group & 1 << s != 0)
+                            .fold(0, |spots, s| spots | 1 << spot_map[s as usize]);
+                        groups.contains(&mapped)
+                    });
+                    if maps_groups {
+                        board_maps.push(BoardMap(spot_map));
+                    }
+                }
+            }
+        }
+        let attribute_maps = permutations(G::ATTRIBUTES as usize);
+        let piece_maps = (0..G::PIECES)
+            .flat_map(|flip| {
+                attribute_maps.iter().map(move |attribute_map| {
+                    PieceMap(
+                        (0..G::PIECES)
+                            .map(|piece| {
+                                (0..G::ATTRIBUTES)
+                                    .filter(|k| (piece ^ flip) >> k & 1 != 0)
+                                    .fold(0, |mapped, k| mapped | 1 << attribute_map[k as usize])
+                            })
+                            .collect(),
+                    )
+                })
+            })
+            .collect();
+        Self {
+            board_maps,
+            piece_maps,
+            identity: PieceMap((0..G::PIECES).collect()),
+            geometry: PhantomData,
+        }
+    }
+
+    /// Number of symmetries of the board
+    pub(crate) fn board_count(&self) -> usize {
+        self.board_maps.len()
+    }
+
+    /// The symmetries of the board
+    pub(crate) fn board_maps(&self) -> &[BoardMap] {
+        &self.board_maps
+    }
+
+    /// The symmetries of the pieces
+    pub(crate) fn piece_maps(&self) -> &[PieceMap] {
+        &self.piece_maps
+    }
+
+    /// A key that's the same for all positions equivalent to this one, and different otherwise
+    ///
+    /// It's the smallest encoding among the equivalent positions where the first piece on the
+    /// board, or the chosen piece on an empty board, has no attributes. Each spot and the chosen
+    /// piece take `ATTRIBUTES + 1` bits, so the key has to fit `(SPOTS + 1) * (ATTRIBUTES + 1)`
+    /// bits.
+    pub(crate) fn canonical_key(&self, position: &Position<G>) -> u128 {
+        let chosen = position.get_chosen_piece();
+        let bits = G::ATTRIBUTES + 1;
+        let attribute_count = self.piece_maps().len() / G::PIECES as usize;
+        let mut best = u128::MAX;
+        for board_map in self.board_maps() {
+            let moved = position.transform(board_map, &self.identity);
+            let board: Vec<Option<Piece>> =
+                (0..G::SPOTS).map(|s| moved.get_piece(Spot(s))).collect();
+            // complement the attributes so that the first piece has none, which leaves the
+            // permutations of the attributes
+            let flip = board
+                .iter()
+                .flatten()
+                .next()
+                .or(chosen.as_ref())
+                .map_or(0, |piece| piece.0);
+            let first = flip as usize * attribute_count;
+            for piece_map in &self.piece_maps()[first..first + attribute_count] {
+                let encode = |piece: Option<Piece>| {
+                    piece.map_or(0, |piece| piece.transform(piece_map).0 as u128 + 1)
+                };
+                let key = board
+                    .iter()
+                    .fold(encode(chosen), |key, piece| key << bits | encode(*piece));
+                best = best.min(key);
+            }
+        }
+        best
+    }
+}
+
+/// All permutations of `0..n`
+fn permutations(n: usize) -> Vec<Vec<i8>> {
+    if n == 0 {
+        return vec![vec![]];
+    }
+    let mut longer = vec![];
+    for shorter in permutations(n - 1) {
+        for k in 0..n {
+            let mut permutation = shorter.clone();
+            permutation.insert(k, n as i8 - 1);
+            longer.push(permutation);
+        }
+    }
+    longer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{geometry::Dims, random_history, rules::Rules, Pcg};
+
+    /// Positions every few turns of a few random games, from the empty board to the end
+    fn sample_positions<G: Geometry>(rules: Rules, games: u32) -> Vec<Position<G>> {
+        let mut pcg = Pcg::new();
+        let mut positions = vec![];
+        for _ in 0..games {
+            let history = random_history::<G>(rules, &mut pcg);
+            positions.extend(
+                (0..=G::LAST_TURN + 1)
+                    .step_by(4)
+                    .map(|turn| history.get_position(turn)),
+            );
+        }
+        positions
+    }
+
+    #[test]
+    fn symmetries_keep_the_quartos() {
+        for (name, board_count) in [("standard", 32), ("squares", 8), ("torus", 16)] {
+            let rules = Rules::parse(name).unwrap();
+            let symmetries = Symmetries::<Dims<4, 4>>::new(rules.squares);
+            let piece_maps = symmetries.piece_maps();
+            assert_eq!(symmetries.board_count(), board_count);
+            assert_eq!(piece_maps.len(), 384);
+            let positions = sample_positions::<Dims<4, 4>>(rules, 3);
+            assert!(positions.iter().any(Position::is_quarto));
+            for position in positions {
+                for board_map in symmetries.board_maps() {
+                    for piece_map in piece_maps {
+                        let transformed = position.transform(board_map, piece_map);
+                        assert_eq!(transformed.is_quarto(), position.is_quarto());
+                        for spot in (0..16).map(Spot) {
+                            assert_eq!(
+                                transformed.is_quarto_at(spot.transform(board_map)),
+                                position.is_quarto_at(spot)
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn equivalent_positions_share_the_canonical_key() {
+        let rules = Rules::standard();
+        let symmetries = Symmetries::<Dims<4, 4>>::new(rules.squares);
+        let piece_maps = symmetries.piece_maps();
+        // the key takes long to compute, so it's checked on a single position from the middle
+        // of a game
+        let position = &sample_positions::<Dims<4, 4>>(rules, 1)[2];
+        let key = symmetries.canonical_key(position);
+        for board_map in symmetries.board_maps() {
+            for piece_map in piece_maps {
+                let transformed = position.transform(board_map, piece_map);
+                assert_eq!(symmetries.canonical_key(&transformed), key);
+            }
+        }
+    }
+}
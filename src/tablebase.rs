@@ -0,0 +1,338 @@
+//! Endgame tablebase, with the exact results of positions close to the end of the game
+//!
+//! A tablebase holds the canonical positions with at most a given number of empty spots that
+//! are reachable from a starting position. Each one comes with its value for the player to move,
+//! and the number of turns until the game ends with perfect play, where the winner ends it as
+//! soon as possible and the loser holds out as long as possible.
+//!
+//! The file starts with a line of text with the format version, the board, the rules and the
+//! number of empty spots. Then come the number of positions as a little-endian `u64`, and the
+//! positions sorted by key, each as the little-endian bytes of its key followed by a byte with
+//! the result. It ends with a checksum of everything before it, as a little-endian `u64`
+//! FNV-1a hash.
+
+use crate::{
+    args::Args,
+    geometry::{Board, Dims, Geometry},
+    rules::Rules,
+    symmetry::Symmetries,
+    Game, Move, Position,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    io::{self, Write},
+    time::Instant,
+};
+
+/// Version of the file format
+const VERSION: u32 = 1;
+
+/// Run the generator with command line arguments of the form
+/// `--empty <N> --out <FILE> [--board <BOARD>] [--rules <RULES>] [<MOVE>...]`
+///
+/// The moves lead from the start of the game to the starting position.
+pub fn run(args: &[String], output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["--empty", "--out"])?;
+    let max_empty = args
+        .flags
+        .get("--empty")
+        .ok_or("missing `--empty`")?
+        .parse()
+        .map_err(|_| "invalid number of empty spots")?;
+    let path = *args.flags.get("--out").ok_or("missing `--out`")?;
+    match args.board {
+        Board::Small => generate::<Dims<3, 3>>(&args, max_empty, path, output),
+        Board::Standard => generate::<Dims<4, 4>>(&args, max_empty, path, output),
+        Board::Large => Err("the board is too large for a tablebase".into()),
+    }
+}
+
+fn generate<G: Geometry>(
+    args: &Args,
+    max_empty: i8,
+    path: &str,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let game = args.replay::<G>()?;
+    let start = Instant::now();
+    let mut generator = Generator {
+        rules: args.rules,
+        max_empty,
+        symmetries: Symmetries::new(args.rules.squares),
+        entries: HashMap::new(),
+        visited: HashSet::new(),
+    };
+    generator.visit(game.position(), game.turn());
+
+    let mut entries: Vec<(u128, u8)> = generator
+        .entries
+        .iter()
+        .map(|(key, outcome)| (*key, outcome.encode()))
+        .collect();
+    entries.sort_unstable();
+    let tablebase = Tablebase {
+        rules: args.rules,
+        max_empty,
+        entries,
+        symmetries: generator.symmetries,
+    };
+    tablebase.save(path)?;
+    writeln!(output, "positions {}", tablebase.entries.len())?;
+    writeln!(output, "time {:.3}s", start.elapsed().as_secs_f32())?;
+    Ok(())
+}
+
+/// Exact result of a position for the player to move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Outcome {
+    /// 1 for a win, 0 for a draw, and -1 for a loss
+    pub(crate) value: i8,
+    /// Number of turns until the game ends
+    pub(crate) distance: u8,
+}
+
+impl Outcome {
+    /// Higher is better for the player to move
+    fn score(&self) -> i32 {
+        self.value as i32 * (u8::MAX as i32 - self.distance as i32)
+    }
+
+    /// The outcome for the player who moved into this position
+    fn previous(&self) -> Self {
+        Outcome {
+            value: -self.value,
+            distance: self.distance + 1,
+        }
+    }
+
+    fn encode(&self) -> u8 {
+        self.distance << 2 | (self.value + 1) as u8
+    }
+
+    fn decode(byte: u8) -> Self {
+        Outcome {
+            value: (byte & 3) as i8 - 1,
+            distance: byte >> 2,
+        }
+    }
+}
+
+/// Where a move of the player to move leads
+pub(crate) enum Next<G: Geometry> {
+    /// The game ends, with this outcome for the player who moved
+    End(Outcome),
+    Position(Position<G>),
+}
+
+/// The moves of the player to move on `turn`, and where they lead
+pub(crate) fn next_positions<G: Geometry>(
+    position: &Position<G>,
+    turn: i8,
+    rules: Rules,
+) -> Vec<(Move, Next<G>)> {
+    position
+        .legal_moves(turn, None)
+        .into_iter()
+        .map(|mv| {
+            let next = position.after_move(&mv);
+            let value = match (mv.spot, mv.piece) {
+                (_, Some(_)) => return (mv, Next::Position(next)),
+                (Some(spot), None) if next.is_quarto_at(spot) => {
+                    if rules.misere {
+                        -1
+                    } else {
+                        1
+                    }
+                }
+                // placing the last piece without a quarto is a draw
+                (_, None) => 0,
+            };
+            (mv, Next::End(Outcome { value, distance: 1 }))
+        })
+        .collect()
+}
+
+/// Number of empty spots on `turn`
+fn empty_spots<G: Geometry>(turn: i8) -> i8 {
+    G::SPOTS - (turn - 1).max(0)
+}
+
+struct Generator<G: Geometry> {
+    rules: Rules,
+    max_empty: i8,
+    symmetries: Symmetries<G>,
+    /// Outcomes of the positions in the tablebase by canonical key
+    entries: HashMap<u128, Outcome>,
+    /// Positions with too many empty spots for the tablebase that were already visited
+    visited: HashSet<u128>,
+}
+
+impl<G: Geometry> Generator<G> {
+    /// Add the positions reachable from `position` on `turn` to the tablebase
+    fn visit(&mut self, position: &Position<G>, turn: i8) {
+        if turn > 0 && empty_spots::<G>(turn) <= self.max_empty {
+            self.outcome(position, turn);
+            return;
+        }
+        if !self.visited.insert(self.symmetries.canonical_key(position)) {
+            return;
+        }
+        for (_, next) in next_positions(position, turn, self.rules) {
+            if let Next::Position(next) = next {
+                self.visit(&next, turn + 1);
+            }
+        }
+    }
+
+    fn outcome(&mut self, position: &Position<G>, turn: i8) -> Outcome {
+        let key = self.symmetries.canonical_key(position);
+        if let Some(outcome) = self.entries.get(&key) {
+            return *outcome;
+        }
+        let best = next_positions(position, turn, self.rules)
+            .into_iter()
+            .map(|(_, next)| match next {
+                Next::End(outcome) => outcome,
+                Next::Position(next) => self.outcome(&next, turn + 1).previous(),
+            })
+            .max_by_key(Outcome::score)
+            .unwrap();
+        self.entries.insert(key, best);
+        best
+    }
+}
+
+pub(crate) struct Tablebase<G: Geometry> {
+    pub(crate) rules: Rules,
+    /// Reachable positions with at most this many empty spots are in the tablebase
+    max_empty: i8,
+    /// Canonical keys with encoded outcomes, sorted by key
+    entries: Vec<(u128, u8)>,
+    symmetries: Symmetries<G>,
+}
+
+impl<G: Geometry> Tablebase<G> {
+    /// Number of bytes of a key in the file
+    const KEY_SIZE: usize = ((G::SPOTS as usize + 1) * (G::ATTRIBUTES as usize + 1) + 7) / 8;
+
+    fn header(rules: Rules, max_empty: i8) -> String {
+        format!(
+            "bruto tablebase {} {} {} empty {}\n",
+            VERSION,
+            G::BOARD,
+            rules,
+            max_empty
+        )
+    }
+
+    fn save(&self, path: &str) -> io::Result<()> {
+        let mut data = Self::header(self.rules, self.max_empty).into_bytes();
+        data.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for (key, outcome) in &self.entries {
+            data.extend_from_slice(&key.to_le_bytes()[..Self::KEY_SIZE]);
+            data.push(*outcome);
+        }
+        data.extend_from_slice(&checksum(&data).to_le_bytes());
+        fs::write(path, data)
+    }
+
+    /// Load a tablebase for this board, checking its version and checksum
+    pub(crate) fn load(path: &str) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let data = fs::read(path)?;
+        if data.len() < 8 {
+            return Err(invalid("the tablebase is truncated"));
+        }
+        let (data, sum) = data.split_at(data.len() - 8);
+        if checksum(data).to_le_bytes() != sum {
+            return Err(invalid("the tablebase is corrupted"));
+        }
+        let line_end = data
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or_else(|| invalid("the tablebase has an invalid header"))?;
+        let header = std::str::from_utf8(&data[..line_end])
+            .map_err(|_| invalid("the tablebase has an invalid header"))?;
+        let words: Vec<&str> = header.split_whitespace().collect();
+        let (rules, max_empty) = match words[..] {
+            ["bruto", "tablebase", version, ..] if version != VERSION.to_string() => {
+                Err(invalid("the tablebase has an unsupported version"))
+            }
+            ["bruto", "tablebase", _, board, ..] if board != G::BOARD.to_string() => {
+                Err(invalid("the tablebase is for another board"))
+            }
+            ["bruto", "tablebase", _, _, ref rules @ .., "empty", max_empty] => {
+                match (Rules::parse(&rules.join(" ")), max_empty.parse()) {
+                    (Some(rules), Ok(max_empty)) => Ok((rules, max_empty)),
+                    _ => Err(invalid("the tablebase has an invalid header")),
+                }
+            }
+            _ => Err(invalid("the tablebase has an invalid header")),
+        }?;
+
+        let records = &data[line_end + 1..];
+        if records.len() < 8 {
+            return Err(invalid("the tablebase is truncated"));
+        }
+        let (count, records) = records.split_at(8);
+        let count = u64::from_le_bytes(count.try_into().unwrap()) as usize;
+        if records.len() != count * (Self::KEY_SIZE + 1) {
+            return Err(invalid("the tablebase is truncated"));
+        }
+        let entries = records
+            .chunks_exact(Self::KEY_SIZE + 1)
+            .map(|record| {
+                let mut key = [0; 16];
+                key[..Self::KEY_SIZE].copy_from_slice(&record[..Self::KEY_SIZE]);
+                (u128::from_le_bytes(key), record[Self::KEY_SIZE])
+            })
+            .collect();
+        Ok(Self {
+            rules,
+            max_empty,
+            entries,
+            symmetries: Symmetries::new(rules.squares),
+        })
+    }
+
+    /// The outcome of `position` on `turn` for the player to move, if it's in the tablebase
+    pub(crate) fn probe(&self, position: &Position<G>, turn: i8) -> Option<Outcome> {
+        if turn == 0 || empty_spots::<G>(turn) > self.max_empty {
+            return None;
+        }
+        let key = self.symmetries.canonical_key(position);
+        let index = self
+            .entries
+            .binary_search_by_key(&key, |(key, _)| *key)
+            .ok()?;
+        Some(Outcome::decode(self.entries[index].1))
+    }
+
+    /// The best move of the player to move, if the tablebase has the outcomes of all moves
+    pub(crate) fn best_move(&self, game: &Game<G>) -> Option<Move> {
+        let turn = game.turn();
+        let mut best: Option<(Outcome, Move)> = None;
+        for (mv, next) in next_positions(game.position(), turn, self.rules) {
+            let outcome = match next {
+                Next::End(outcome) => outcome,
+                Next::Position(next) => self.probe(&next, turn + 1)?.previous(),
+            };
+            if best
+                .as_ref()
+                .map_or(true, |(best, _)| outcome.score() > best.score())
+            {
+                best = Some((outcome, mv));
+            }
+        }
+        best.map(|(_, mv)| mv)
+    }
+}
+
+/// 64-bit FNV-1a hash
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}